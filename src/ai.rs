@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+use bevy::tasks::{block_on, AsyncComputeTaskPool};
+use std::sync::atomic::Ordering;
+
+use crate::board::*;
+use crate::config::*;
+use crate::game::*;
+use crate::pieces::*;
+use crate::replay::*;
+use crate::resource_allocation::*;
+use crate::rules::*;
+use crate::rules::Move;
+use crate::search::*;
+use crate::notation::parse_uci;
+use crate::uci::*;
+
+
+/// Starts a search on the async compute pool when it is the computer's turn and
+/// plays the move it comes back with, so input and rendering never wait on it.
+#[allow(clippy::too_many_arguments)]
+pub fn ai_move(
+    mut board_query: Query<&mut ChessBoard, BoardFilter>,
+    mut pieces_query: PieceQuery,
+    mut game_state: ResMut<GameState>,
+    mut move_history: ResMut<MoveHistory>,
+    game_result: Res<GameResult>,
+    replay: Res<Replay>,
+    ai_difficulty: Res<AiDifficulty>,
+    external: Res<ExternalEngine>,
+    book: Res<Book>,
+    tablebase: Res<Tablebase>,
+    mut ai_player: ResMut<AiPlayer>,
+) {
+    let side_to_move = game_state.position.side_to_move();
+    let engine_plays = external.engine.is_some() && external.mode == EngineMode::Opponent;
+    if ai_player.color != Some(side_to_move) || game_result.outcome.is_some() || replay.is_active() || engine_plays {
+        if ai_player.task.is_some() {
+            ai_player.cancel_search();
+        }
+        return;
+    }
+
+    let fen = game_state.position.to_fen();
+    match &ai_player.task {
+        None => {
+            // Few enough pieces left for the tablebases to know the perfect move
+            if let Some(tablebases) = tablebase.tablebases.as_ref().filter(|tablebases| tablebases.covers(&game_state.position)) {
+                match tablebases.best_move(&game_state.position) {
+                    Ok(Some((mv, result))) => {
+                        info!("Computer plays {mv} from the tablebases ({:?}, DTZ {})", result.wdl, result.dtz);
+                        let mut board_component = board_query.single_mut();
+                        play_computer_move(&mut board_component, &mut pieces_query, &mut game_state, &mut move_history, mv);
+                        return;
+                    }
+                    Ok(None) => {}
+                    Err(error) => debug!("Tablebase probe failed, searching instead: {error}"),
+                }
+            }
+
+            // Play straight from the book while the game is still in it
+            if let Some(mv) = book.book.as_ref().and_then(|opening_book| opening_book.pick(&game_state.position, book.selection)) {
+                info!("Computer plays {mv} from the book");
+                let mut board_component = board_query.single_mut();
+                play_computer_move(&mut board_component, &mut pieces_query, &mut game_state, &mut move_history, mv);
+                return;
+            }
+
+            let mut position = game_state.position.clone();
+            let limits = ai_difficulty.limits;
+            let mut searcher = ai_player.searcher.take().unwrap_or_default();
+            let stop_flag = searcher.stop_flag();
+            stop_flag.store(false, Ordering::Relaxed); // still set if the last search was cancelled
+            ai_player.stop_flag = Some(stop_flag);
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let result = searcher.search(&mut position, limits);
+                (searcher, result)
+            });
+            ai_player.task = Some((fen, task));
+            return;
+        }
+        // The board was changed under the search (reset, new FEN), start over next frame
+        Some((task_fen, _)) if *task_fen != fen => {
+            ai_player.cancel_search();
+            return;
+        }
+        Some((_, task)) if !task.is_finished() => return,
+        Some(_) => {}
+    }
+
+    let Some((_, task)) = ai_player.task.take() else { return };
+    let (searcher, result) = block_on(task);
+    ai_player.searcher = Some(searcher);
+    ai_player.stop_flag = None;
+    let Some(mv) = result.best_move else { return };
+    info!("Computer plays {mv} (score {}, depth {}, {} nodes)", result.score, result.depth, result.nodes);
+
+    let mut board_component = board_query.single_mut();
+    play_computer_move(&mut board_component, &mut pieces_query, &mut game_state, &mut move_history, mv);
+}
+
+/// Drives the external UCI engine. As the opponent it is asked for a move whenever
+/// it is the computer's turn; as an analyser it thinks about every position shown.
+#[allow(clippy::too_many_arguments)]
+pub fn external_engine(
+    mut board_query: Query<&mut ChessBoard, BoardFilter>,
+    mut pieces_query: PieceQuery,
+    mut game_state: ResMut<GameState>,
+    mut move_history: ResMut<MoveHistory>,
+    game_result: Res<GameResult>,
+    replay: Res<Replay>,
+    ai_player: Res<AiPlayer>,
+    ai_difficulty: Res<AiDifficulty>,
+    mut external: ResMut<ExternalEngine>,
+) {
+    if external.starting.as_ref().is_some_and(|(_, task)| task.is_finished())
+        && let Some((path, task)) = external.starting.take()
+    {
+        match block_on(task) {
+            Ok(engine) => {
+                info!("Started UCI engine {}", engine.name().unwrap_or(&path));
+                external.engine = Some(engine);
+            }
+            Err(error) => warn!("Could not start UCI engine {path}: {error}"),
+        }
+    }
+
+    // Polling happens every frame, only flag a change when there is something new to show
+    let state = external.bypass_change_detection();
+    let mut changed = false;
+    let Some(engine) = state.engine.as_mut() else { return };
+
+    let fen = game_state.position.to_fen();
+    let side_to_move = game_state.position.side_to_move();
+    let computer_to_move = ai_player.color == Some(side_to_move) && game_result.outcome.is_none() && !replay.is_active();
+
+    let result = engine.poll().and_then(|events| {
+        for event in events {
+            let for_current_position = state.searching.as_ref() == Some(&fen);
+            match event {
+                UciEvent::Info(info) if for_current_position && info.score.is_some() => {
+                    state.analysis = Some((fen.clone(), info));
+                    changed = true;
+                }
+                UciEvent::Info(_) => {}
+                UciEvent::BestMove { best, .. } => {
+                    state.searching = None;
+                    if !for_current_position || state.mode != EngineMode::Opponent || !computer_to_move {
+                        continue;
+                    }
+                    match parse_uci(&game_state.position, &best) {
+                        Ok(mv) => {
+                            info!("{} plays {mv}", engine.name().unwrap_or("UCI engine"));
+                            let mut board_component = board_query.single_mut();
+                            play_computer_move(&mut board_component, &mut pieces_query, &mut game_state, &mut move_history, mv);
+                        }
+                        Err(error) => {
+                            warn!("UCI engine answered with {best}: {error}");
+                            state.mode = EngineMode::Off;
+                            changed = true;
+                        }
+                    }
+                    return Ok(());
+                }
+            }
+        }
+
+        let wanted = match state.mode {
+            EngineMode::Opponent if computer_to_move => Some(GoLimit::MoveTime(ai_difficulty.limits.time_limit)),
+            EngineMode::Analysis if game_result.outcome.is_none() => Some(GoLimit::Infinite),
+            _ => None,
+        };
+
+        // A search on a position that is gone has to finish before the next one can start
+        if engine.is_searching() {
+            if wanted.is_none() || state.searching.as_ref() != Some(&fen) {
+                engine.stop()?;
+            }
+        } else if let Some(limit) = wanted {
+            let moves: Vec<Move> = move_history.moves.iter().map(|(mv, _)| *mv).collect();
+            engine.go(&move_history.start_fen, &moves, limit)?;
+            state.searching = Some(fen.clone());
+        }
+        Ok(())
+    });
+
+    if let Err(error) = result {
+        warn!("UCI engine stopped: {error}");
+        *state = ExternalEngine::default();
+        changed = true;
+    }
+    if changed {
+        external.set_changed();
+    }
+}
+
+/// Plays a move the computer chose through the same path as a move made with the mouse.
+fn play_computer_move(
+    board_component: &mut ChessBoard,
+    pieces_query: &mut PieceQuery,
+    game_state: &mut GameState,
+    move_history: &mut MoveHistory,
+    mv: Move,
+) {
+    let Some(entity) = board_component.piece_at(mv.from) else { return };
+
+    let moved = process_piece_movement(
+        board_component,
+        pieces_query,
+        &mut game_state.position,
+        move_history,
+        (entity, mv.from),
+        mv.to,
+        mv.promotion,
+    );
+    if moved {
+        game_state.selected_piece = None;
+        game_state.pending_promotion = None;
+    }
+}
@@ -0,0 +1,57 @@
+//! Speed benchmark: perft and a fixed-depth search over a few standard positions,
+//! reporting nodes per second for each.
+//!
+//! Run as `chess_bevy --bench [search depth]`, ideally from a release build.
+
+use std::time::{Duration, Instant};
+
+use crate::fen::STARTING_FEN;
+use crate::perft::perft;
+use crate::rules::*;
+use crate::search::*;
+
+const PERFT_DEPTH: u32 = 4;
+const DEFAULT_SEARCH_DEPTH: u32 = 6;
+
+const POSITIONS: [(&str, &str); 6] = [
+    ("start", STARTING_FEN),
+    ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"),
+    ("endgame", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"),
+    ("promotions", "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1"),
+    ("position 5", "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8"),
+    ("middlegame", "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10"),
+];
+
+/// Command line entry point; `arguments` are the ones after `--bench`.
+pub fn run(arguments: &[String]) {
+    let search_depth = arguments.first()
+        .and_then(|depth| depth.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_SEARCH_DEPTH);
+    let limits = SearchLimits { max_depth: search_depth, ..SearchLimits::timed(Duration::from_secs(24 * 60 * 60)) };
+
+    let mut perft_total = (0, Duration::ZERO);
+    let mut search_total = (0, Duration::ZERO);
+    println!("{:<12} {:>22} {:>22}", "position", format!("perft {PERFT_DEPTH} nps"), format!("search {search_depth} nps"));
+
+    for (name, fen) in POSITIONS {
+        let mut position = Position::from_fen(fen).expect("benchmark positions are valid");
+
+        let start = Instant::now();
+        let perft_nodes = perft(&mut position, PERFT_DEPTH);
+        let perft_time = start.elapsed();
+
+        let start = Instant::now();
+        let result = Searcher::new().search(&mut position, limits);
+        let search_time = start.elapsed();
+
+        println!("{name:<12} {:>22} {:>22}", nodes_per_second(perft_nodes, perft_time), nodes_per_second(result.nodes, search_time));
+        perft_total = (perft_total.0 + perft_nodes, perft_total.1 + perft_time);
+        search_total = (search_total.0 + result.nodes, search_total.1 + search_time);
+    }
+
+    println!("{:<12} {:>22} {:>22}", "total", nodes_per_second(perft_total.0, perft_total.1), nodes_per_second(search_total.0, search_total.1));
+}
+
+fn nodes_per_second(nodes: u64, elapsed: Duration) -> u64 {
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 
 use crate::config::*;
+use crate::resource_allocation::GameState;
+use crate::pieces::Piece;
+use crate::rules::Square;
 
 
 
@@ -8,9 +11,25 @@ use crate::config::*;
 pub struct ChessBoard {
     pub pieces: [[Option<Entity>; 8]; 8],
     pub tiles: [[Option<Entity>; 8]; 8],
-    pub current_size: f32,
 }
 
+impl ChessBoard {
+    pub fn piece_at(&self, square: Square) -> Option<Entity> {
+        self.pieces[square.row()][square.col()]
+    }
+
+    pub fn set_piece(&mut self, square: Square, entity: Option<Entity>) {
+        self.pieces[square.row()][square.col()] = entity;
+    }
+
+    pub fn take_piece(&mut self, square: Square) -> Option<Entity> {
+        self.pieces[square.row()][square.col()].take()
+    }
+}
+
+/// Selects the board entity in queries that also borrow the pieces' transforms.
+pub type BoardFilter = (With<ChessBoard>, Without<Piece>);
+
 #[derive(Component)]
 
 pub struct Tile {
@@ -19,15 +38,44 @@ pub struct Tile {
     pub color: Color,
 }
 
-// fn tint_color(base: Color, tint: Color) -> Color {
-//     let color_vec = BOARD_COLOR_DARK.as_rgba_f32();
-//     Color::srgba(
-//         base.r() * tint.r(),
-//         base.g() * tint.g(),
-//         base.b() * tint.b(),
-//         base.a() * tint.a()
-//     )
-// }
+/// Marks a square the selected piece can move to, spawned as a child of its tile.
+#[derive(Component)]
+pub struct MoveMarker;
+
+/// Materials and meshes the tiles switch between when a piece is selected.
+#[derive(Resource)]
+pub struct TileMaterials {
+    pub light: Handle<ColorMaterial>,
+    pub dark: Handle<ColorMaterial>,
+    pub selected_light: Handle<ColorMaterial>,
+    pub selected_dark: Handle<ColorMaterial>,
+    pub marker: Handle<ColorMaterial>,
+    pub dot: Handle<Mesh>, // quiet move
+    pub ring: Handle<Mesh>, // capture, drawn around the piece
+}
+
+fn tint_color(base: Color, tint: Color) -> Color {
+    let base = base.to_srgba();
+    let tint = tint.to_srgba();
+    Color::srgba(
+        base.red * tint.red,
+        base.green * tint.green,
+        base.blue * tint.blue,
+        base.alpha * tint.alpha
+    )
+}
+
+fn is_light(row: usize, col: usize) -> bool {
+    (1 + row + col).is_multiple_of(2)
+}
+
+/// Centre of the tile at (row, col) in the board's local space.
+pub fn tile_center(row: usize, col: usize) -> Vec2 {
+    Vec2::new(
+        (col as f32 * TILE_SIZE) - MARGIN + (TILE_SIZE/2.0),
+        (row as f32 * TILE_SIZE) - MARGIN + (TILE_SIZE/2.0),
+    )
+}
 
 
 pub fn create_board(
@@ -39,9 +87,15 @@ pub fn create_board(
     let tile_mesh = meshes.add(Rectangle::new(TILE_SIZE, TILE_SIZE));
     let white_material = materials.add(BOARD_COLOR_LIGHT);
     let black_material = materials.add(BOARD_COLOR_DARK);
-    // println!("{:?}", BOARD_COLOR_DARK);
-    // materials.add(BOARD_COLOR_SELECTED_DARK);
-    // materials.add(BOARD_COLOR_SELECTED_LIGHT);
+    commands.insert_resource(TileMaterials {
+        light: white_material.clone(),
+        dark: black_material.clone(),
+        selected_light: materials.add(tint_color(BOARD_COLOR_LIGHT, BOARD_SELECTED_TINT)),
+        selected_dark: materials.add(tint_color(BOARD_COLOR_DARK, BOARD_SELECTED_TINT)),
+        marker: materials.add(MOVE_MARKER_COLOR),
+        dot: meshes.add(Circle::new(TILE_SIZE * MOVE_DOT_RADIUS)),
+        ring: meshes.add(Annulus::new(TILE_SIZE * (MOVE_RING_RADIUS - MOVE_RING_WIDTH), TILE_SIZE * MOVE_RING_RADIUS)),
+    });
 
 
     let mut tiles: [[Option<Entity>; 8]; 8] = [[None; 8]; 8];
@@ -51,19 +105,15 @@ pub fn create_board(
     // Generate board squares
     for row in 0..8 {
         for col in 0..8 {
-            let position = Vec3::new(
-                (col as f32 * TILE_SIZE) - MARGIN + (TILE_SIZE/2.0),
-                (row as f32 * TILE_SIZE) - MARGIN + (TILE_SIZE/2.0),
-                0.0
-            );
+            let position = tile_center(row, col).extend(0.0);
 
-            let calc = (1 + row + col) % 2;
+            let light = is_light(row, col);
 
             let tile_id =commands.spawn((
                 Mesh2d(tile_mesh.clone()),
-                MeshMaterial2d(if calc == 0 { white_material.clone() } else { black_material.clone() }),
+                MeshMaterial2d(if light { white_material.clone() } else { black_material.clone() }),
                 Transform::from_translation(position),
-                Tile { row, col, color: if calc == 0 { BOARD_COLOR_LIGHT } else { BOARD_COLOR_DARK } },
+                Tile { row, col, color: if light { BOARD_COLOR_LIGHT } else { BOARD_COLOR_DARK } },
             )).id();
 
             tiles[row][col] = Some(tile_id);
@@ -75,7 +125,7 @@ pub fn create_board(
     let board = commands.spawn((
         Transform::default(), 
         Visibility::default(), 
-        ChessBoard { pieces: [[None; 8]; 8], tiles: tiles , current_size: TILE_SIZE * 8.0 }
+        ChessBoard { pieces: [[None; 8]; 8], tiles: tiles }
     )).id();
 
     // set board as parent to all tiles
@@ -136,3 +186,54 @@ pub fn reset_board_scale(
     board_transform.scale = Vec3::splat(1.0);
     board_transform.translation = Vec3::splat(0.0);
 }
+
+
+/// Tints the selected piece's square and marks where it can go: a dot on empty squares,
+/// a ring around pieces it can capture. Rebuilt whenever the game state changes.
+pub fn update_move_highlights(
+    mut commands: Commands,
+    game_state: Res<GameState>,
+    board_query: Query<&ChessBoard>,
+    mut tiles_query: Query<(&Tile, &mut MeshMaterial2d<ColorMaterial>)>,
+    markers_query: Query<Entity, With<MoveMarker>>,
+    tile_materials: Option<Res<TileMaterials>>,
+) {
+    let Some(tile_materials) = tile_materials else { return };
+    if !game_state.is_changed() {
+        return;
+    }
+    let Ok(board) = board_query.get_single() else { return };
+
+    for marker in markers_query.iter() {
+        commands.entity(marker).despawn();
+    }
+    for (tile, mut material) in tiles_query.iter_mut() {
+        let plain = if is_light(tile.row, tile.col) { &tile_materials.light } else { &tile_materials.dark };
+        if material.0 != *plain {
+            material.0 = plain.clone();
+        }
+    }
+
+    let Some((_, selected)) = game_state.selected_piece else { return };
+    let tile_at = |square: Square| board.tiles[square.row()][square.col()];
+
+    if let Some(Ok((_, mut material))) = tile_at(selected).map(|tile| tiles_query.get_mut(tile)) {
+        let (row, col) = selected.into();
+        material.0 = if is_light(row, col) { tile_materials.selected_light.clone() } else { tile_materials.selected_dark.clone() };
+    }
+
+    let mut destinations: Vec<(Square, bool)> = game_state.position.moves_from(selected).iter()
+        .map(|mv| (mv.to, game_state.position.piece_at(mv.capture_square()).is_some()))
+        .collect();
+    destinations.dedup(); // promotions come four to a square
+    for (square, capture) in destinations {
+        let Some(tile) = tile_at(square) else { continue };
+        let marker = commands.spawn((
+            Mesh2d(if capture { tile_materials.ring.clone() } else { tile_materials.dot.clone() }),
+            MeshMaterial2d(tile_materials.marker.clone()),
+            Transform::from_xyz(0.0, 0.0, 0.5), // above the tile, below the piece
+            MoveMarker,
+        )).id();
+        commands.entity(tile).add_child(marker);
+    }
+}
@@ -0,0 +1,240 @@
+//! Polyglot opening books (`.bin`): a sorted list of 16-byte big-endian entries,
+//! each a position key, a move and a weight. Keys are `Position::hash`.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::rules::*;
+
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum BookError {
+    Io(io::Error),
+    Truncated(usize), // file length, not a multiple of the entry size
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookError::Io(error) => write!(f, "{error}"),
+            BookError::Truncated(length) => write!(f, "{length} bytes is not a whole number of book entries"),
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+impl From<io::Error> for BookError {
+    fn from(error: io::Error) -> Self {
+        BookError::Io(error)
+    }
+}
+
+/// How a move is picked when the book knows several.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookSelection {
+    Best, // always the highest weight
+    Weighted, // at random, in proportion to the weights
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BookEntry {
+    key: u64,
+    mv: u16,
+    weight: u16,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OpeningBook {
+    entries: Vec<BookEntry>, // sorted by key
+}
+
+impl OpeningBook {
+    pub fn load(path: &Path) -> Result<Self, BookError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BookError> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(BookError::Truncated(bytes.len()));
+        }
+
+        let mut entries: Vec<BookEntry> = bytes.chunks_exact(ENTRY_SIZE).map(|entry| BookEntry {
+            key: u64::from_be_bytes(entry[0..8].try_into().unwrap()),
+            mv: u16::from_be_bytes([entry[8], entry[9]]),
+            weight: u16::from_be_bytes([entry[10], entry[11]]),
+            // the last four bytes are Polyglot's learning data, unused
+        }).collect();
+        // Books are written sorted already, this only guards against hand-made ones
+        entries.sort_by_key(|entry| entry.key);
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Book moves for `position` with their weights, highest weight first.
+    /// Entries that do not decode to a legal move are left out.
+    pub fn moves(&self, position: &Position) -> Vec<(Move, u16)> {
+        let key = position.hash();
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let legal_moves = position.legal_moves();
+
+        let mut moves: Vec<(Move, u16)> = self.entries[start..].iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| Some((decode_move(entry.mv, &legal_moves)?, entry.weight)))
+            .collect();
+        moves.sort_by_key(|(_, weight)| std::cmp::Reverse(*weight));
+        moves
+    }
+
+    /// A move to play from the book, or None once the game has left it.
+    pub fn pick(&self, position: &Position, selection: BookSelection) -> Option<Move> {
+        let moves = self.moves(position);
+        match selection {
+            BookSelection::Best => moves.first().map(|(mv, _)| *mv),
+            BookSelection::Weighted => {
+                let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+                if total == 0 {
+                    return moves.first().map(|(mv, _)| *mv);
+                }
+                let mut roll = fastrand::u32(..total);
+                for (mv, weight) in moves {
+                    if roll < weight as u32 {
+                        return Some(mv);
+                    }
+                    roll -= weight as u32;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Finds the legal move a Polyglot move stands for. Bits 0-5 hold the destination,
+/// 6-11 the origin (file, then rank) and 12-14 the promotion piece. Castling is
+/// written as the king taking its own rook, e1h1 for white's short castle.
+fn decode_move(encoded: u16, legal_moves: &[Move]) -> Option<Move> {
+    let square = |bits: u16| Square::new(((bits >> 3) & 7) as usize, (bits & 7) as usize);
+    let to = square(encoded);
+    let from = square(encoded >> 6);
+    let promotion = match (encoded >> 12) & 7 {
+        0 => None,
+        1 => Some(PieceType::Knight),
+        2 => Some(PieceType::Bishop),
+        3 => Some(PieceType::Rook),
+        4 => Some(PieceType::Queen),
+        _ => return None,
+    };
+
+    legal_moves.iter().copied().find(|mv| {
+        let destination_matches = match mv.castling_rook() {
+            Some((rook_from, _)) => rook_from == to,
+            None => mv.to == to,
+        };
+        mv.from == from && destination_matches && mv.promotion == promotion
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_uci;
+
+    fn encode(position: &Position, text: &str) -> u16 {
+        let mv = parse_uci(position, text).unwrap();
+        let to = match mv.castling_rook() {
+            Some((rook_from, _)) => rook_from,
+            None => mv.to,
+        };
+        let promotion = match mv.promotion {
+            None => 0,
+            Some(PieceType::Knight) => 1,
+            Some(PieceType::Bishop) => 2,
+            Some(PieceType::Rook) => 3,
+            _ => 4,
+        };
+        (promotion << 12) | ((mv.from.row() << 9) | (mv.from.col() << 6) | (to.row() << 3) | to.col()) as u16
+    }
+
+    /// A book with an entry for each (key, position the move is played in, move, weight).
+    fn book_with_keys(entries: &[(u64, &Position, &str, u16)]) -> OpeningBook {
+        let mut bytes = Vec::new();
+        for (key, position, text, weight) in entries {
+            bytes.extend_from_slice(&key.to_be_bytes());
+            bytes.extend_from_slice(&encode(position, text).to_be_bytes());
+            bytes.extend_from_slice(&weight.to_be_bytes());
+            bytes.extend_from_slice(&[0; 4]);
+        }
+        OpeningBook::from_bytes(&bytes).unwrap()
+    }
+
+    /// Keyed with `Position::hash`, which is the key Polyglot itself writes.
+    fn book(entries: &[(&Position, &str, u16)]) -> OpeningBook {
+        let entries: Vec<_> = entries.iter().map(|&(position, text, weight)| (position.hash(), position, text, weight)).collect();
+        book_with_keys(&entries)
+    }
+
+    #[test]
+    fn finds_moves_under_published_polyglot_keys() {
+        let start = Position::starting();
+        let mut after_e4 = start.clone();
+        after_e4.make_move(parse_uci(&start, "e2e4").unwrap());
+        let book = book_with_keys(&[
+            (0x463b96181691fc9c, &start, "e2e4", 20),
+            (0x823c9b50fd114196, &after_e4, "c7c5", 10),
+        ]);
+        assert_eq!(book.pick(&start, BookSelection::Best).unwrap().to_string(), "e2e4");
+        assert_eq!(book.pick(&after_e4, BookSelection::Best).unwrap().to_string(), "c7c5");
+    }
+
+    #[test]
+    fn looks_up_moves_by_position() {
+        let start = Position::starting();
+        let mut after_e4 = start.clone();
+        after_e4.make_move(parse_uci(&start, "e2e4").unwrap());
+        let book = book(&[(&after_e4, "c7c5", 30), (&start, "d2d4", 10), (&start, "e2e4", 20)]);
+
+        let moves: Vec<(String, u16)> = book.moves(&start).iter().map(|(mv, weight)| (mv.to_string(), *weight)).collect();
+        assert_eq!(moves, [("e2e4".to_string(), 20), ("d2d4".to_string(), 10)]);
+        assert_eq!(book.pick(&start, BookSelection::Best).unwrap().to_string(), "e2e4");
+        assert_eq!(book.pick(&after_e4, BookSelection::Weighted).unwrap().to_string(), "c7c5");
+
+        let mut out_of_book = after_e4.clone();
+        out_of_book.make_move(parse_uci(&after_e4, "e7e5").unwrap());
+        assert!(book.pick(&out_of_book, BookSelection::Weighted).is_none());
+    }
+
+    #[test]
+    fn weighted_pick_skips_zero_weights() {
+        let start = Position::starting();
+        let book = book(&[(&start, "e2e4", 1), (&start, "a2a3", 0)]);
+        for _ in 0..20 {
+            assert_eq!(book.pick(&start, BookSelection::Weighted).unwrap().to_string(), "e2e4");
+        }
+    }
+
+    #[test]
+    fn decodes_castling_and_promotion() {
+        let castling = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let promotion = Position::from_fen("8/P6k/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let book = book(&[(&castling, "e1g1", 5), (&castling, "e1c1", 3), (&promotion, "a7a8n", 1)]);
+
+        let castles: Vec<String> = book.moves(&castling).iter().map(|(mv, _)| mv.to_string()).collect();
+        assert_eq!(castles, ["e1g1", "e1c1"]);
+        assert_eq!(book.moves(&promotion)[0].0.promotion, Some(PieceType::Knight));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        assert!(matches!(OpeningBook::from_bytes(&[0; 20]), Err(BookError::Truncated(20))));
+        assert!(OpeningBook::from_bytes(&[]).unwrap().is_empty());
+    }
+}
@@ -1,5 +1,8 @@
 use bevy::prelude::*;
 
+use crate::book::BookSelection;
+use crate::search::Difficulty;
+
 pub const TARGET_FPS: u8 = 60;
 
 pub const WINDOW_TITLE: &str = "Bevy Chess";
@@ -18,11 +21,40 @@ pub const BOARD_SCALE_MIN: f32 = 0.2;
 pub const BOARD_COLOR_DARK: Color = Color::srgba(0.18, 0.15, 0.10, 1.0);
 pub const BOARD_COLOR_LIGHT: Color = Color::srgba(0.85, 0.82, 0.75, 1.0);
 pub const BOARD_SELECTED_TINT: Color = Color::srgba(0.5, 1.0, 0.5, 1.0);
+pub const MOVE_MARKER_COLOR: Color = Color::srgba(0.1, 0.1, 0.1, 0.35);
+pub const MOVE_DOT_RADIUS: f32 = 0.15; // in tiles
+pub const MOVE_RING_RADIUS: f32 = 0.48; // in tiles, outer edge
+pub const MOVE_RING_WIDTH: f32 = 0.07; // in tiles
 // pub const BOARD_COLOR_SELECTED_DARK: Color = Color::srgba(0.1, 0.3, 0.1, 1.0);
 // pub const BOARD_COLOR_SELECTED_LIGHT: Color = Color::srgba(0.8, 0.9, 0.7, 1.0);
 
 
+pub const AUTO_QUEEN: bool = false;
+pub const PGN_EXPORT_DIR: &str = "games";
+
+pub const AI_DIFFICULTY: Difficulty = Difficulty::Medium;
+pub const UCI_ENGINE_PATH: &str = ""; // external engine binary, `--engine <path>` overrides it
+pub const OPENING_BOOK_PATH: &str = "assets/books/book.bin"; // optional Polyglot book, none ships with the game; `--book <path>` overrides it
+pub const BOOK_SELECTION: BookSelection = BookSelection::Weighted;
+pub const BOOK_MOVES_SHOWN: usize = 5; // book moves listed in the side panel
+pub const SYZYGY_PATH: &str = ""; // directory of Syzygy tables, `--syzygy <dir>` overrides it
+
+pub const HUD_MARGIN: f32 = 20.0;
+pub const HUD_FONT_SIZE: f32 = 24.0;
+pub const HUD_TEXT_COLOR: Color = Color::srgba(0.9, 0.9, 0.9, 1.0);
+pub const HUD_HIGHLIGHT_COLOR: Color = Color::srgba(0.95, 0.75, 0.3, 1.0);
+pub const HUD_BUTTON_COLOR: Color = Color::srgba(0.25, 0.25, 0.25, 1.0);
+pub const HUD_BUTTON_HOVER_COLOR: Color = Color::srgba(0.4, 0.4, 0.4, 1.0);
+pub const HUD_BUTTON_SELECTED_COLOR: Color = Color::srgba(0.55, 0.42, 0.15, 1.0);
+pub const PROMOTION_BUTTON_SIZE: f32 = 64.0;
+pub const MOVE_LIST_LINES: usize = 12; // full moves shown in the side panel
+
+
 const PIECE_ORIG_SIZE: f32 = 125.0;
 
+pub const DRAG_THRESHOLD: f32 = 4.0; // board-space pixels the cursor moves before a press becomes a drag
+pub const DRAGGED_PIECE_Z: f32 = 2.0; // above every other piece
+pub const PIECE_RETURN_TIME: f32 = 0.15; // seconds for a dropped piece to slide back
+
 pub const PIECE_SCALE: f32 = TILE_SIZE * 0.7 / PIECE_ORIG_SIZE;
 
@@ -0,0 +1,257 @@
+//! Forsyth-Edwards Notation import and export for [`Position`].
+
+use std::fmt;
+
+use crate::rules::*;
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    WrongFieldCount(usize),
+    BadBoard(String),
+    BadSideToMove(String),
+    BadCastling(String),
+    BadEnPassant(String),
+    BadClock(String),
+    MissingKing(PieceColor),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::WrongFieldCount(count) => write!(f, "expected 4 to 6 fields, found {count}"),
+            FenError::BadBoard(board) => write!(f, "invalid piece placement '{board}'"),
+            FenError::BadSideToMove(side) => write!(f, "invalid side to move '{side}'"),
+            FenError::BadCastling(castling) => write!(f, "invalid castling rights '{castling}'"),
+            FenError::BadEnPassant(square) => write!(f, "invalid en passant square '{square}'"),
+            FenError::BadClock(clock) => write!(f, "invalid move clock '{clock}'"),
+            FenError::MissingKing(color) => write!(f, "no {color:?} king on the board"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+impl Position {
+    /// Parses a FEN string. The two move clocks may be left out and default to `0 1`.
+    pub fn from_fen(fen: &str) -> Result<Position, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if !(4..=6).contains(&fields.len()) {
+            return Err(FenError::WrongFieldCount(fields.len()));
+        }
+
+        let mut position = Position::empty();
+
+        // Piece placement, rank 8 first
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::BadBoard(fields[0].to_string()));
+        }
+        for (rank_index, rank) in ranks.iter().enumerate() {
+            let row = 7 - rank_index;
+            let mut col = 0;
+            for symbol in rank.chars() {
+                if let Some(empty) = symbol.to_digit(10) {
+                    col += empty as usize;
+                } else {
+                    let piece = piece_from_char(symbol).ok_or_else(|| FenError::BadBoard(fields[0].to_string()))?;
+                    if col >= 8 {
+                        return Err(FenError::BadBoard(fields[0].to_string()));
+                    }
+                    position.set_piece(Square::new(row, col), Some(piece));
+                    col += 1;
+                }
+            }
+            if col != 8 {
+                return Err(FenError::BadBoard(fields[0].to_string()));
+            }
+        }
+
+        for color in [PieceColor::White, PieceColor::Black] {
+            if position.king_square(color).is_none() {
+                return Err(FenError::MissingKing(color));
+            }
+        }
+
+        let side_to_move = match fields[1] {
+            "w" => PieceColor::White,
+            "b" => PieceColor::Black,
+            other => return Err(FenError::BadSideToMove(other.to_string())),
+        };
+        position.set_side_to_move(side_to_move);
+
+        let mut castling = CastlingRights::NONE;
+        if fields[2] != "-" {
+            for symbol in fields[2].chars() {
+                match symbol {
+                    'K' => castling.white_king_side = true,
+                    'Q' => castling.white_queen_side = true,
+                    'k' => castling.black_king_side = true,
+                    'q' => castling.black_queen_side = true,
+                    _ => return Err(FenError::BadCastling(fields[2].to_string())),
+                }
+            }
+        }
+        position.set_castling_rights(castling);
+
+        if fields[3] != "-" {
+            let square: Square = fields[3].parse().map_err(|_| FenError::BadEnPassant(fields[3].to_string()))?;
+            let expected_row = match side_to_move {
+                PieceColor::White => 5,
+                PieceColor::Black => 2,
+            };
+            if square.row() != expected_row {
+                return Err(FenError::BadEnPassant(fields[3].to_string()));
+            }
+            position.set_en_passant(Some(square));
+        }
+
+        let parse_clock = |field: Option<&&str>, default: u32| match field {
+            Some(value) => value.parse::<u32>().map_err(|_| FenError::BadClock(value.to_string())),
+            None => Ok(default),
+        };
+        let halfmove_clock = parse_clock(fields.get(4), 0)?;
+        let fullmove_number = parse_clock(fields.get(5), 1)?.max(1);
+        position.set_clocks(halfmove_clock, fullmove_number);
+
+        Ok(position)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::with_capacity(90);
+
+        for row in (0..8).rev() {
+            let mut empty = 0;
+            for col in 0..8 {
+                match self.piece_at(Square::new(row, col)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece_to_char(piece));
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if row > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push(' ');
+        fen.push(match self.side_to_move() {
+            PieceColor::White => 'w',
+            PieceColor::Black => 'b',
+        });
+
+        fen.push(' ');
+        let castling = self.castling_rights();
+        let rights = [
+            (castling.white_king_side, 'K'),
+            (castling.white_queen_side, 'Q'),
+            (castling.black_king_side, 'k'),
+            (castling.black_queen_side, 'q'),
+        ];
+        if rights.iter().any(|(allowed, _)| *allowed) {
+            fen.extend(rights.iter().filter(|(allowed, _)| *allowed).map(|(_, symbol)| symbol));
+        } else {
+            fen.push('-');
+        }
+
+        fen.push(' ');
+        match self.en_passant() {
+            Some(square) => fen.push_str(&square.to_string()),
+            None => fen.push('-'),
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove_clock(), self.fullmove_number()));
+        fen
+    }
+}
+
+pub fn piece_from_char(symbol: char) -> Option<BoardPiece> {
+    let color = if symbol.is_ascii_uppercase() { PieceColor::White } else { PieceColor::Black };
+    let piece_type = match symbol.to_ascii_lowercase() {
+        'p' => PieceType::Pawn,
+        'n' => PieceType::Knight,
+        'b' => PieceType::Bishop,
+        'r' => PieceType::Rook,
+        'q' => PieceType::Queen,
+        'k' => PieceType::King,
+        _ => return None,
+    };
+    Some(BoardPiece::new(piece_type, color))
+}
+
+pub fn piece_to_char(piece: BoardPiece) -> char {
+    let symbol = match piece.piece_type {
+        PieceType::Pawn | PieceType::Empty => 'p',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Rook => 'r',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
+    };
+    match piece.color {
+        PieceColor::White => symbol.to_ascii_uppercase(),
+        PieceColor::Black => symbol,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        for fen in [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w Kq f6 0 3",
+            "8/8/4k3/8/8/8/4K3/8 b - - 47 90",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn reads_the_fields() {
+        let position = Position::from_fen("4k3/8/8/8/3pP3/8/8/4K2R b K e3 5 40").unwrap();
+        assert_eq!(position.side_to_move(), PieceColor::Black);
+        assert_eq!(position.piece_at(Square::new(0, 7)).map(piece_to_char), Some('R'));
+        assert_eq!(position.fullmove_number(), 40);
+        assert!(position.legal_moves().iter().any(|mv| mv.kind == MoveKind::EnPassant));
+    }
+
+    #[test]
+    fn move_clocks_are_optional() {
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - -").unwrap();
+        assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        let cases = [
+            ("", FenError::WrongFieldCount(0)),
+            ("8/8/8/8/8/8/8/8 w - - 0 1 extra", FenError::WrongFieldCount(7)),
+            ("4k3/8/8/8/8/8/8 w - - 0 1", FenError::BadBoard("4k3/8/8/8/8/8/8".to_string())),
+            ("4k4/8/8/8/8/8/8/4K3 w - - 0 1", FenError::BadBoard("4k4/8/8/8/8/8/8/4K3".to_string())),
+            ("4k3/8/8/8/8/8/8/4K2 w - - 0 1", FenError::BadBoard("4k3/8/8/8/8/8/8/4K2".to_string())),
+            ("4k3/8/8/8/8/8/8/4X3 w - - 0 1", FenError::BadBoard("4k3/8/8/8/8/8/8/4X3".to_string())),
+            ("4k3/8/8/8/8/8/8/8 w - - 0 1", FenError::MissingKing(PieceColor::White)),
+            ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::BadSideToMove("x".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w KX - 0 1", FenError::BadCastling("KX".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - e3 0 1", FenError::BadEnPassant("e3".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - z9 0 1", FenError::BadEnPassant("z9".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - -1 1", FenError::BadClock("-1".to_string())),
+        ];
+        for (fen, error) in cases {
+            assert_eq!(Position::from_fen(fen).map(|position| position.to_fen()), Err(error), "{fen}");
+        }
+    }
+}
@@ -2,11 +2,16 @@ use std::process::exit;
 
 use bevy::prelude::*;
 
+use crate::config::*;
 use crate::resource_allocation::*;
 use crate::board::*;
 use crate::pieces::*;
-
-use std::time::Instant;
+use crate::rules::*;
+use crate::ui::*;
+use crate::notation::*;
+use crate::pgn::*;
+use crate::replay::*;
+use crate::ai::*;
 
 pub struct GamePlugin;
 
@@ -14,8 +19,39 @@ impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app 
             .add_systems(PreStartup, resource_allocation)
-            .add_systems(Startup, (create_camera, create_board, create_pieces).chain())
-            .add_systems(Update, (keyboard_input, mouse_input));
+            .add_systems(Startup, (create_camera, create_board, create_pieces, create_ui).chain())
+            .add_systems(Update, (
+                (
+                    keyboard_input,
+                    clipboard_input,
+                    undo_input,
+                    pgn_export_input,
+                    replay_input,
+                    promotion_input,
+                    difficulty_input,
+                    mouse_input,
+                    animate_returning_pieces,
+                    ai_move,
+                    external_engine,
+                    check_game_over,
+                ).chain(),
+                // Display only, runs after the moves of this frame are in
+                (
+                    update_piece_sprites,
+                    update_move_highlights,
+                    update_turn_indicator,
+                    update_game_over_text,
+                    update_draw_claim_text,
+                    update_promotion_overlay,
+                    update_difficulty_buttons,
+                    update_settings_text,
+                    update_replay_text,
+                    update_move_list_text,
+                    update_engine_text,
+                    update_book_text,
+                    update_tablebase_text,
+                ).chain(),
+            ).chain());
     }
 }
 
@@ -34,10 +70,16 @@ pub fn create_camera(
     ));
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn keyboard_input(
     keyboard: Res<ButtonInput<KeyCode>>,
     mut board_query: Query<&mut Transform, With<ChessBoard>>,
-    board_settings: Res<BoardSettings>
+    board_settings: Res<BoardSettings>,
+    mut game_settings: ResMut<GameSettings>,
+    game_state: Res<GameState>,
+    mut game_result: ResMut<GameResult>,
+    mut ai_player: ResMut<AiPlayer>,
+    mut external: ResMut<ExternalEngine>,
 ) {
     let board_transform = board_query.single_mut();
     if keyboard.just_pressed(KeyCode::KeyR) {
@@ -56,165 +98,663 @@ pub fn keyboard_input(
 
     }
 
+    if keyboard.just_pressed(KeyCode::KeyQ) {
+        game_settings.auto_queen = !game_settings.auto_queen;
+    }
+
+    // Cycle the computer opponent: off, plays black, plays white
+    if keyboard.just_pressed(KeyCode::KeyA) {
+        ai_player.color = match ai_player.color {
+            None => Some(PieceColor::Black),
+            Some(PieceColor::Black) => Some(PieceColor::White),
+            Some(PieceColor::White) => None,
+        };
+    }
+
+    // Cycle what the external UCI engine is used for: nothing, the computer's moves, analysis
+    if keyboard.just_pressed(KeyCode::KeyE) {
+        if let Some((path, _)) = &external.starting {
+            warn!("UCI engine {path} is still starting");
+        } else if external.engine.is_none() {
+            warn!("No UCI engine running, start the game with --engine <path>");
+        } else {
+            external.mode = match external.mode {
+                EngineMode::Off => EngineMode::Opponent,
+                EngineMode::Opponent => EngineMode::Analysis,
+                EngineMode::Analysis => EngineMode::Off,
+            };
+        }
+    }
+
+    // Claim a fifty-move or threefold repetition draw when one is available
+    if keyboard.just_pressed(KeyCode::KeyD)
+        && game_result.outcome.is_none()
+        && let Some(reason) = game_state.position.claimable_draw()
+    {
+        game_result.outcome = Some(GameOutcome::Draw(reason));
+    }
+
     if keyboard.just_pressed(KeyCode::Escape) {
         std::process::exit(0);
     }
 
 }
 
+/// Ctrl+C copies the current position as FEN, Ctrl+V loads a FEN from the clipboard.
+#[allow(clippy::too_many_arguments)]
+pub fn clipboard_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut commands: Commands,
+    mut board_query: Query<(Entity, &mut ChessBoard), With<ChessBoard>>,
+    pieces_query: Query<Entity, With<Piece>>,
+    mut game_state: ResMut<GameState>,
+    mut game_result: ResMut<GameResult>,
+    mut move_history: ResMut<MoveHistory>,
+    mut replay: ResMut<Replay>,
+    piece_sprites: Res<PieceSprites>,
+) {
+    if !keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::KeyC) {
+        let fen = game_state.position.to_fen();
+        match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(fen.clone())) {
+            Ok(()) => info!("Copied FEN: {fen}"),
+            Err(error) => warn!("Could not copy FEN to the clipboard: {error}"),
+        }
+    } else if keyboard.just_pressed(KeyCode::KeyV) {
+        let text = match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+            Ok(text) => text,
+            Err(error) => {
+                warn!("Could not read the clipboard: {error}");
+                return;
+            }
+        };
+
+        match Position::from_fen(text.trim()) {
+            Ok(position) => {
+                *replay = Replay::default();
+                let (board_entity, mut board_component) = board_query.single_mut();
+                load_position(
+                    &mut commands,
+                    board_entity,
+                    &mut board_component,
+                    pieces_query.iter(),
+                    &mut game_state,
+                    &mut game_result,
+                    &mut move_history,
+                    &piece_sprites,
+                    position,
+                );
+                info!("Loaded FEN: {}", text.trim());
+            }
+            Err(error) => warn!("Clipboard does not hold a valid FEN: {error}"),
+        }
+    }
+}
+
+/// Replaces the game with `position`: despawns every piece entity, spawns the new
+/// layout and resets selection and result to match.
+#[allow(clippy::too_many_arguments)]
+pub fn load_position(
+    commands: &mut Commands,
+    board_entity: Entity,
+    board_component: &mut ChessBoard,
+    piece_entities: impl Iterator<Item = Entity>,
+    game_state: &mut GameState,
+    game_result: &mut GameResult,
+    move_history: &mut MoveHistory,
+    piece_sprites: &PieceSprites,
+    position: Position,
+) {
+    for entity in piece_entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    board_component.pieces = [[None; 8]; 8];
+
+    spawn_pieces(commands, board_entity, board_component, &position, piece_sprites);
+
+    game_state.selected_piece = None;
+    game_state.selected_tile = None;
+    game_state.pending_promotion = None;
+    move_history.start_fen = position.to_fen();
+    move_history.moves.clear();
+    move_history.records.clear();
+    move_history.redo.clear();
+    game_state.position = position;
+    game_result.outcome = None;
+}
+
+/// Ctrl+S writes the game played so far as PGN into the configured directory.
+pub fn pgn_export_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    move_history: Res<MoveHistory>,
+    game_result: Res<GameResult>,
+    game_settings: Res<GameSettings>,
+) {
+    if !keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) || !keyboard.just_pressed(KeyCode::KeyS) {
+        return;
+    }
+
+    let moves = move_history.moves.iter().map(|(_, san)| san.clone()).collect();
+    let game = PgnGame::new(&move_history.start_fen, moves, game_result.outcome);
+    match write_pgn(&game_settings.pgn_directory, &game) {
+        Ok(path) => info!("Saved PGN to {}", path.display()),
+        Err(error) => warn!("Could not save PGN: {error}"),
+    }
+}
+
+/// Picks pieces up and puts them down. A piece can be clicked and then its destination
+/// clicked, or dragged there with the button held; a drop off any legal square sends it back.
+#[allow(clippy::too_many_arguments)]
 pub fn mouse_input(
     window: Query<&Window>,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
     mut commands: Commands, // Add commands for deferred operations
-    mut board_query: Query<(&mut ChessBoard, &Transform), (With<ChessBoard>, Without<Piece>)>,
-    mut pieces_query: Query<(&mut Transform, &mut Piece, &mut Visibility), With<Piece>>, // Only query for read access initially
+    mut board_query: Query<(&mut ChessBoard, &GlobalTransform), BoardFilter>,
+    camera_query: Query<(&bevy::prelude::Camera, &GlobalTransform), With<Camera>>,
+    mut pieces_query: PieceQuery, // Only query for read access initially
     mut game_state: ResMut<GameState>,
+    game_result: Res<GameResult>,
+    game_settings: Res<GameSettings>,
+    mut move_history: ResMut<MoveHistory>,
+    replay: Res<Replay>,
+    ai_player: Res<AiPlayer>,
+    mut drag: ResMut<PieceDrag>,
 ) {
-    if !mouse_button_input.just_pressed(MouseButton::Left) {
+    // Pieces only move by stepping through the game while a replay is shown,
+    // nor can they be moved for the computer
+    let locked = game_result.outcome.is_some() || replay.is_active() || ai_player.color == Some(game_state.position.side_to_move());
+    if locked {
+        if let Some((entity, _)) = drag.piece.take() {
+            return_piece(&mut commands, &mut pieces_query, entity);
+        }
         return;
     }
-    let start = Instant::now();
 
     let window = window.single();
-    let Some(mouse_position) = window.cursor_position() else { return };
-    let mouse_position = Vec2::new(mouse_position.x as f32, mouse_position.y as f32);
-
-    // Get board information
+    let (camera, camera_transform) = camera_query.single();
     let (mut board_component, board_transform) = board_query.single_mut();
-    let board_size = board_component.current_size;
-    let board_position = Vec2::new(board_transform.translation.x, board_transform.translation.y);
-
-    // Calculate tile position
-    let tile_size = board_size / 8.0;
-    let mouse_pos_board = mouse_position - board_position;
-
-    // Check if click is within board bounds
-    if mouse_pos_board.x < 0.0 || 
-       mouse_pos_board.y < 0.0 || 
-       mouse_pos_board.x > board_size || 
-       mouse_pos_board.y > board_size {
+    let cursor = cursor_board_position(window, camera, camera_transform, board_transform);
+
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        // Check if click is within board bounds
+        let Some((cursor, clicked)) = cursor.and_then(|cursor| Some((cursor, square_at(cursor)?))) else {
+            game_state.selected_piece = None;
+            return;
+        };
+
+        // Clicking the board while the promotion picker is open cancels the move
+        if game_state.pending_promotion.is_some() {
+            game_state.pending_promotion = None;
+            return;
+        }
+
+        // Only the side to move may pick up a piece
+        let own_piece = board_component.piece_at(clicked).filter(|&entity| {
+            pieces_query
+                .get(entity)
+                .is_ok_and(|(_, piece, _)| piece.piece_color == game_state.position.side_to_move())
+        });
+
+        if let Some(piece_entity) = own_piece {
+            game_state.selected_piece = Some((piece_entity, clicked));
+            *drag = PieceDrag { piece: Some((piece_entity, clicked)), grab_point: cursor, dragged: false };
+            commands.entity(piece_entity).remove::<ReturningPiece>();
+        } else if let Some(selected_piece) = game_state.selected_piece {
+            try_move(&mut board_component, &mut pieces_query, &mut game_state, &game_settings, &mut move_history, selected_piece, clicked);
+            game_state.selected_piece = None;
+        }
+    } else if mouse_button_input.pressed(MouseButton::Left) {
+        // The held piece follows the cursor once it has really been moved, not on every click
+        let (Some((entity, _)), Some(cursor)) = (drag.piece, cursor) else { return };
+        if !drag.dragged && cursor.distance(drag.grab_point) < DRAG_THRESHOLD {
+            return;
+        }
+        drag.dragged = true;
+        if let Ok((mut transform, _, _)) = pieces_query.get_mut(entity) {
+            transform.translation = cursor.extend(DRAGGED_PIECE_Z);
+        }
+    } else if mouse_button_input.just_released(MouseButton::Left) {
+        let Some(selected_piece) = drag.piece.take() else { return };
+        // A release without dragging finishes a click, the piece stays selected
+        if !std::mem::take(&mut drag.dragged) {
+            return;
+        }
+
+        let (entity, from) = selected_piece;
+        if let Ok((mut transform, _, _)) = pieces_query.get_mut(entity) {
+            transform.translation.z = 1.0;
+        }
+
+        // Dropped on its own square: put it back and keep it selected for a click
+        let target = cursor.and_then(square_at);
+        if target == Some(from) {
+            return_piece(&mut commands, &mut pieces_query, entity);
+            return;
+        }
+
+        let moved = target.is_some_and(|to| {
+            try_move(&mut board_component, &mut pieces_query, &mut game_state, &game_settings, &mut move_history, selected_piece, to)
+        });
+        if !moved {
+            return_piece(&mut commands, &mut pieces_query, entity);
+        }
         game_state.selected_piece = None;
-        return;
     }
+}
 
-    let tile_x: usize = (mouse_pos_board.x / tile_size).floor() as usize;
-    let tile_y: usize = 7 - (mouse_pos_board.y / tile_size).floor() as usize;
+/// Plays the selected piece to `to` for the player, opening the promotion picker when a
+/// pawn reaches the last rank and auto-queen is off. Returns false for illegal moves.
+fn try_move(
+    board_component: &mut ChessBoard,
+    pieces_query: &mut PieceQuery,
+    game_state: &mut GameState,
+    game_settings: &GameSettings,
+    move_history: &mut MoveHistory,
+    selected_piece: (Entity, Square),
+    to: Square,
+) -> bool {
+    let (_, from) = selected_piece;
+    let mut promotion = None;
+    if game_state.position.is_promotion(from, to) {
+        if !game_settings.auto_queen {
+            game_state.pending_promotion = Some((selected_piece, to));
+            return false;
+        }
+        promotion = Some(PieceType::Queen);
+    }
+
+    process_piece_movement(
+        board_component,
+        pieces_query,
+        &mut game_state.position,
+        move_history,
+        selected_piece,
+        to,
+        promotion,
+    )
+}
+
+/// Where the cursor is in the board's local space, the space `tile_center` and the
+/// pieces' transforms use. None when the cursor is outside the window.
+pub fn cursor_board_position(
+    window: &Window,
+    camera: &bevy::prelude::Camera, // `Camera` in this module is our marker component
+    camera_transform: &GlobalTransform,
+    board_transform: &GlobalTransform,
+) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    // The camera accounts for its own position and the window's y axis pointing down
+    let world = camera.viewport_to_world_2d(camera_transform, cursor).ok()?;
+    Some(world_to_board(world, board_transform))
+}
+
+/// Brings a world point into the board's local space, undoing its zoom, offset and any rotation.
+pub fn world_to_board(world: Vec2, board_transform: &GlobalTransform) -> Vec2 {
+    board_transform.affine().inverse().transform_point3(world.extend(0.0)).truncate()
+}
+
+/// The square under a point in the board's local space, None off the board.
+pub fn square_at(point: Vec2) -> Option<Square> {
+    let col = ((point.x + MARGIN) / TILE_SIZE).floor();
+    let row = ((point.y + MARGIN) / TILE_SIZE).floor();
+    if (0.0..8.0).contains(&col) && (0.0..8.0).contains(&row) {
+        Some(Square::new(row as usize, col as usize))
+    } else {
+        None
+    }
+}
+
+/// Slides a piece that was let go of somewhere it cannot stay back onto its square.
+fn return_piece(
+    commands: &mut Commands,
+    pieces_query: &mut PieceQuery,
+    entity: Entity,
+) {
+    if let Ok((mut transform, _, _)) = pieces_query.get_mut(entity) {
+        transform.translation.z = 1.0;
+        commands.entity(entity).insert(ReturningPiece { start: transform.translation.truncate(), elapsed: 0.0 });
+    }
+}
+
+pub fn promotion_input(
+    interaction_query: Query<(&Interaction, &PromotionChoice), Changed<Interaction>>,
+    mut board_query: Query<&mut ChessBoard, BoardFilter>,
+    mut pieces_query: PieceQuery,
+    mut game_state: ResMut<GameState>,
+    mut move_history: ResMut<MoveHistory>,
+) {
+    let Some((selected_piece, new_pos)) = game_state.pending_promotion else { return };
+
+    for (interaction, choice) in interaction_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        let mut board_component = board_query.single_mut();
 
-    if let Some(selected_piece) = game_state.selected_piece {
         process_piece_movement(
-            &mut commands,
             &mut board_component,
             &mut pieces_query,
+            &mut game_state.position,
+            &mut move_history,
             selected_piece,
-            (tile_y, tile_x),
-            board_position,
-            tile_size,
+            new_pos,
+            Some(choice.0),
         );
-        game_state.selected_piece = None;
-    } else if let Some(piece_entity) = board_component.pieces[tile_y][tile_x] {
-        game_state.selected_piece = Some((piece_entity, (tile_y, tile_x)));
+        game_state.pending_promotion = None;
+        return;
     }
+}
 
-    println!("Time taken: {:?}", start.elapsed());
+/// Picks the computer's strength from the difficulty buttons; it applies from the computer's next move.
+pub fn difficulty_input(
+    interaction_query: Query<(&Interaction, &DifficultyChoice), Changed<Interaction>>,
+    mut ai_difficulty: ResMut<AiDifficulty>,
+) {
+    for (interaction, choice) in interaction_query.iter() {
+        if *interaction == Interaction::Pressed && choice.0 != ai_difficulty.level {
+            *ai_difficulty = AiDifficulty::new(choice.0);
+            info!("Computer difficulty: {}", choice.0.name());
+        }
+    }
 }
 
-fn process_piece_movement(
-    commands: &mut Commands,
-    board_component: &mut ChessBoard,
-    pieces_query: &mut Query<(&mut Transform, &mut Piece, &mut Visibility), With<Piece>>,
-    selected_piece: (Entity, (usize, usize)),
-    new_pos: (usize, usize),
-    board_position: Vec2,
-    tile_size: f32,
+pub fn check_game_over(
+    game_state: Res<GameState>,
+    mut game_result: ResMut<GameResult>,
 ) {
-    let (selected_entity, old_pos) = selected_piece;
-    let (tile_y, tile_x) = new_pos;
-    
-    // First, validate the move without mutable borrows
-    let can_move = if let Ok((_, piece, _)) = pieces_query.get(selected_entity) {
-        let dst_entity = &board_component.pieces[tile_y][tile_x];
-        is_valid_move(&piece, old_pos, (tile_y, tile_x), dst_entity)
-    } else {
-        false
-    };
-    
-    if !can_move {
+    if !game_state.is_changed() || game_result.outcome.is_some() {
         return;
     }
-    
-    // Handle capture if needed
-    let dst_entity = board_component.pieces[tile_y][tile_x];
-    if let Some(capture_entity) = dst_entity {
+
+    if let Some(outcome) = game_state.position.outcome() {
+        game_result.outcome = Some(outcome);
+    }
+}
+
+pub fn process_piece_movement(
+    board_component: &mut ChessBoard,
+    pieces_query: &mut PieceQuery,
+    position: &mut Position,
+    move_history: &mut MoveHistory,
+    selected_piece: (Entity, Square),
+    new_pos: Square,
+    promotion: Option<PieceType>,
+) -> bool {
+    let (selected_entity, old_pos) = selected_piece;
+
+    // Ask the rules core whether the move exists before touching any entity
+    let Some(mv) = position.find_move(old_pos, new_pos, promotion) else {
+        return false;
+    };
+    let san = move_to_san(position, mv);
+    info!("{}{} {san} ({mv})", position.fullmove_number(), if position.side_to_move() == PieceColor::White { "." } else { "..." });
+    move_history.moves.push((mv, san));
+    move_history.redo.clear();
+    position.make_move(mv);
+
+    let has_moved = |pieces_query: &PieceQuery, entity: Entity| {
+        pieces_query.get(entity).is_ok_and(|(_, piece, _)| piece.has_moved)
+    };
+    let mut record = MoveRecord {
+        mv,
+        piece: selected_entity,
+        had_moved: has_moved(pieces_query, selected_entity),
+        captured: None,
+        rook: None,
+    };
+
+    // Handle capture if needed. En passant takes the pawn beside us, not the one on the destination
+    if let Some(capture_entity) = board_component.take_piece(mv.capture_square()) {
         if let Ok((_, mut capture_piece, mut capture_visibility)) = pieces_query.get_mut(capture_entity) {
             capture_piece.is_captured = true;
             *capture_visibility = Visibility::Hidden;
         }
+        record.captured = Some(capture_entity);
     }
-    
+
     // Now update the moving piece
-    if let Ok((mut transform, mut piece, _)) = pieces_query.get_mut(selected_entity) {
-        // Calculate new position
-        let new_x = board_position.x + (tile_x as f32 * tile_size) + (tile_size / 2.0);
-        let new_y = board_position.y + (tile_y as f32 * tile_size) + (tile_size / 2.0);
-        
-        // Update the transform and piece directly
-        transform.translation = Vec3::new(new_x, new_y, 0.0);
+    move_piece_entity(board_component, pieces_query, selected_entity, old_pos, new_pos);
+
+    // A promoted pawn becomes the chosen piece, its sprite follows in `update_piece_sprites`
+    if let Some(promotion) = mv.promotion
+        && let Ok((_, mut piece, _)) = pieces_query.get_mut(selected_entity)
+    {
+        piece.piece_type = promotion;
+    }
+
+    // Castling also relocates the rook
+    if let Some((rook_from, rook_to)) = mv.castling_rook()
+        && let Some(rook_entity) = board_component.piece_at(rook_from)
+    {
+        record.rook = Some((rook_entity, has_moved(pieces_query, rook_entity)));
+        move_piece_entity(board_component, pieces_query, rook_entity, rook_from, rook_to);
+    }
+
+    move_history.records.push(record);
+    true
+}
+
+/// Takes back the last move played on the board: the pieces go back to their squares with
+/// their old `has_moved`, a captured piece is shown again and the position is unmade.
+/// The move is kept for `redo_move`. Returns false when there is nothing to undo.
+pub fn undo_move(
+    board_component: &mut ChessBoard,
+    pieces_query: &mut PieceQuery,
+    game_state: &mut GameState,
+    move_history: &mut MoveHistory,
+) -> bool {
+    let Some(record) = move_history.records.pop() else { return false };
+    let mv = record.mv;
+    move_history.moves.pop();
+    move_history.redo.push(mv);
+    game_state.position.unmake_move();
+
+    move_piece_entity(board_component, pieces_query, record.piece, mv.to, mv.from);
+    if let Ok((_, mut piece, _)) = pieces_query.get_mut(record.piece) {
+        piece.has_moved = record.had_moved;
+        if mv.promotion.is_some() {
+            piece.piece_type = PieceType::Pawn;
+        }
+    }
+
+    if let (Some((rook_from, rook_to)), Some((rook_entity, had_moved))) = (mv.castling_rook(), record.rook) {
+        move_piece_entity(board_component, pieces_query, rook_entity, rook_to, rook_from);
+        if let Ok((_, mut rook, _)) = pieces_query.get_mut(rook_entity) {
+            rook.has_moved = had_moved;
+        }
+    }
+
+    // The captured piece never left its square, it only has to be shown and put back on the board
+    if let Some(captured_entity) = record.captured {
+        if let Ok((_, mut captured, mut visibility)) = pieces_query.get_mut(captured_entity) {
+            captured.is_captured = false;
+            *visibility = Visibility::Inherited;
+        }
+        board_component.set_piece(mv.capture_square(), Some(captured_entity));
+    }
+
+    game_state.selected_piece = None;
+    game_state.pending_promotion = None;
+    true
+}
+
+/// Plays the move most recently taken back with `undo_move` again.
+pub fn redo_move(
+    board_component: &mut ChessBoard,
+    pieces_query: &mut PieceQuery,
+    game_state: &mut GameState,
+    move_history: &mut MoveHistory,
+) -> bool {
+    let Some(mv) = move_history.redo.pop() else { return false };
+    let Some(entity) = board_component.piece_at(mv.from) else { return false };
+
+    // Playing a move clears the redo stack, the rest of it still has to be redoable afterwards
+    let remaining = std::mem::take(&mut move_history.redo);
+    let moved = process_piece_movement(
+        board_component,
+        pieces_query,
+        &mut game_state.position,
+        move_history,
+        (entity, mv.from),
+        mv.to,
+        mv.promotion,
+    );
+    move_history.redo = remaining;
+    if moved {
+        game_state.selected_piece = None;
+        game_state.pending_promotion = None;
+    }
+    moved
+}
+
+/// Ctrl+Z takes the last move back, Ctrl+Y (or Ctrl+Shift+Z) plays it again. Against the
+/// computer both go as far as the next position where it is the player's turn.
+#[allow(clippy::too_many_arguments)]
+pub fn undo_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut board_query: Query<&mut ChessBoard, BoardFilter>,
+    mut pieces_query: PieceQuery,
+    mut game_state: ResMut<GameState>,
+    mut game_result: ResMut<GameResult>,
+    mut move_history: ResMut<MoveHistory>,
+    replay: Res<Replay>,
+    ai_player: Res<AiPlayer>,
+) {
+    if !keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) || replay.is_active() {
+        return;
+    }
+    let shift = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let undo = keyboard.just_pressed(KeyCode::KeyZ) && !shift;
+    let redo = keyboard.just_pressed(KeyCode::KeyY) || (keyboard.just_pressed(KeyCode::KeyZ) && shift);
+    if !undo && !redo {
+        return;
+    }
+
+    let mut board_component = board_query.single_mut();
+    let computer_to_move = |game_state: &GameState| ai_player.color == Some(game_state.position.side_to_move());
+
+    let mut changed = false;
+    loop {
+        let stepped = if undo {
+            undo_move(&mut board_component, &mut pieces_query, &mut game_state, &mut move_history)
+        } else {
+            redo_move(&mut board_component, &mut pieces_query, &mut game_state, &mut move_history)
+        };
+        changed |= stepped;
+        if !stepped || !computer_to_move(&game_state) {
+            break;
+        }
+    }
+
+    // Whatever ended the game may not have happened yet, `check_game_over` looks again
+    if changed && undo {
+        game_result.outcome = None;
+    }
+}
+
+fn move_piece_entity(
+    board_component: &mut ChessBoard,
+    pieces_query: &mut PieceQuery,
+    entity: Entity,
+    old_pos: Square,
+    new_pos: Square,
+) {
+    let (row, col) = new_pos.into();
+
+    if let Ok((mut transform, mut piece, _)) = pieces_query.get_mut(entity) {
+        // Pieces are children of the board, so the tile centre holds at any zoom
+        transform.translation = tile_center(row, col).extend(transform.translation.z);
         piece.has_moved = true;
-        piece.position = (tile_y, tile_x);
-        
+        piece.position = new_pos;
+
         // Update the board data structure
-        let (old_y, old_x) = old_pos;
-        board_component.pieces[old_y][old_x] = None;
-        board_component.pieces[tile_y][tile_x] = Some(selected_entity);
+        board_component.set_piece(old_pos, None);
+        board_component.set_piece(new_pos, Some(entity));
     }
 }
 
-pub fn is_valid_move(
-    piece: &Piece,
-    old_pos: (usize, usize),
-    new_pos: (usize, usize),
-    dst_entity: &Option<Entity>,
-) -> bool {
-    //// if the piece is not moved, return false
-    if old_pos == new_pos {
-        return false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// The board's transform after `scale_board` has zoomed it to `scale`.
+    fn zoomed(scale: f32) -> GlobalTransform {
+        let offset = BOARD_SIZE / 2.0 * (1.0 - scale);
+        GlobalTransform::from(Transform::from_xyz(offset, offset, 0.0).with_scale(Vec3::splat(scale)))
     }
 
+    fn square_under(world: Vec2, board: &GlobalTransform) -> Option<Square> {
+        square_at(world_to_board(world, board))
+    }
 
-    //// if piece is pawn
-    if piece.piece_type == PieceType::Pawn {
-        let (old_y, old_x) = old_pos;
-        let (new_y, new_x) = new_pos;
-        let y_diff = new_y as i32 - old_y as i32;
-        let x_diff = new_x as i32 - old_x as i32;
-        
-        //// white pawn
-        println!("Checking move for piece at {:?} from {:?} to {:?}", piece.piece_color, old_pos, new_pos);
-        if piece.piece_color == PieceColor::White {
-            return match (y_diff, x_diff, dst_entity.is_some(), piece.has_moved) {
-                (1, 0, false, _) => true,  // Forward one step (empty square)
-                (2, 0, false, false) => true,  // Forward two steps (empty square, first move)
-                (1, x, true, _) if x.abs() == 1 => true,  // Capture diagonally
-                _ => false
-            };
+    /// Every tile centre, taken out to world space and back, lands on its own square.
+    fn assert_round_trip(board: &GlobalTransform) {
+        for row in 0..8 {
+            for col in 0..8 {
+                let world = board.transform_point(tile_center(row, col).extend(0.0)).truncate();
+                assert_eq!(square_under(world, board), Some(Square::new(row, col)));
+            }
         }
-        //// black pawn
-        else {
-            return match (y_diff, x_diff, dst_entity.is_some(), piece.has_moved) {
-                (-1, 0, false, _) => true,  // Forward one step (empty square)
-                (-2, 0, false, false) => true,  // Forward two steps (empty square, first move)
-                (-1, x, true, _) if x.abs() == 1 => true,  // Capture diagonally
-                _ => false
-            };
+    }
+
+    #[test]
+    fn maps_squares_at_every_zoom() {
+        for scale in [1.0, 0.5, 0.75, 1.25, 2.0] {
+            assert_round_trip(&zoomed(scale));
         }
     }
-    //// if piece is rook
-    //// if piece is knight
-    //// if piece is bishop
-    //// if piece is queen
-    //// if piece is king
 
-    // println!("Checking move for piece at {:?} from {:?} to {:?}", piece_entity, old_pos, new_pos);
-    // true
-    false
-}
\ No newline at end of file
+    #[test]
+    fn zoomed_board_keeps_its_centre() {
+        let centre = Vec2::splat(BOARD_SIZE / 2.0);
+        let just_off = Vec2::splat(TILE_SIZE * 0.1);
+        for scale in [0.5, 1.0, 2.0] {
+            let board = zoomed(scale);
+            assert_eq!(square_under(centre + just_off, &board), Some(Square::new(4, 4)));
+            assert_eq!(square_under(centre - just_off, &board), Some(Square::new(3, 3)));
+        }
+    }
+
+    #[test]
+    fn off_board_points_have_no_square() {
+        let board = zoomed(0.5);
+        // At half size the board covers the middle half of its old area
+        assert_eq!(square_under(Vec2::splat(BOARD_SIZE * 0.2), &board), None);
+        assert_eq!(square_under(Vec2::splat(BOARD_SIZE * 0.8), &board), None);
+        assert_eq!(square_under(Vec2::splat(BOARD_SIZE * 0.3), &board), Some(Square::new(0, 0)));
+    }
+
+    #[test]
+    fn reset_undoes_the_zoom() {
+        let world = tile_center(2, 5);
+        let mut transform = Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::splat(1.5));
+        transform.translation = Vec3::splat(BOARD_SIZE / 2.0 * (1.0 - 1.5));
+        assert_ne!(square_under(world, &GlobalTransform::from(transform)), Some(Square::new(2, 5)));
+
+        transform.scale = Vec3::splat(1.0);
+        transform.translation = Vec3::ZERO;
+        assert_eq!(square_under(world, &GlobalTransform::from(transform)), Some(Square::new(2, 5)));
+    }
+
+    #[test]
+    fn flipped_board_mirrors_the_squares() {
+        // Turned half way round its centre, a1 sits where h8 was
+        let centre = Vec3::new(BOARD_SIZE / 2.0 - MARGIN, BOARD_SIZE / 2.0 - MARGIN, 0.0);
+        for scale in [1.0, 0.5] {
+            let board = GlobalTransform::from(
+                Transform::from_translation(centre)
+                    .with_rotation(Quat::from_rotation_z(PI))
+                    .with_scale(Vec3::splat(scale))
+                    * Transform::from_translation(-centre),
+            );
+            assert_round_trip(&board);
+            let top_right = centre.truncate() + Vec2::splat(TILE_SIZE * 3.5 * scale);
+            assert_eq!(square_under(top_right, &board), Some(Square::new(0, 0)));
+        }
+    }
+}
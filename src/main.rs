@@ -7,6 +7,25 @@ pub mod resource_allocation;
 pub mod pieces;
 pub mod board;
 pub mod game;
+pub mod rules;
+pub mod bitboard;
+pub mod zobrist;
+pub mod book;
+pub mod syzygy;
+pub mod tablebase;
+pub mod search;
+pub mod ai;
+pub mod uci;
+pub mod uci_server;
+pub mod perft;
+pub mod bench;
+pub mod fen;
+pub mod notation;
+pub mod pgn;
+pub mod replay;
+pub mod ui;
+#[cfg(test)]
+pub mod test_files;
 
 
 use bevy::prelude::*;
@@ -19,6 +38,26 @@ use config::*;
 // }
 
 fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+
+    // Engine only, for other chess GUIs and tournament tools: no window, no Bevy
+    if arguments.iter().any(|arg| arg == "--uci") {
+        uci_server::run();
+        return;
+    }
+
+    // Move generator check, see perft.rs
+    if let Some(at) = arguments.iter().position(|arg| arg == "--perft") {
+        perft::run(&arguments[at + 1..]);
+        return;
+    }
+
+    // Move generation and search speed, see bench.rs
+    if let Some(at) = arguments.iter().position(|arg| arg == "--bench") {
+        bench::run(&arguments[at + 1..]);
+        return;
+    }
+
     App::new()
         .add_plugins(
             (DefaultPlugins.set(WindowPlugin {
@@ -35,6 +74,7 @@ fn main() {
 
             )
         )
+        .insert_resource(resource_allocation::PathSettings::from_arguments(&arguments))
         .insert_resource(bevy::winit::WinitSettings {
             focused_mode: bevy::winit::UpdateMode::Reactive {
                 wait: std::time::Duration::from_secs_f64(1.0 / TARGET_FPS as f64),
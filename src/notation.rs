@@ -0,0 +1,319 @@
+//! Chess notation for squares and moves: algebraic square names,
+//! Standard Algebraic Notation (SAN) and the long algebraic form used by UCI.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::rules::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotationError {
+    Invalid(String),
+    Illegal(String),
+    Ambiguous(String),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::Invalid(text) => write!(f, "'{text}' is not valid notation"),
+            NotationError::Illegal(text) => write!(f, "'{text}' is not a legal move here"),
+            NotationError::Ambiguous(text) => write!(f, "'{text}' matches more than one legal move"),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+/// Algebraic name of a square, `a1` to `h8`.
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", (b'a' + self.col() as u8) as char, self.row() + 1)
+    }
+}
+
+impl FromStr for Square {
+    type Err = NotationError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let invalid = || NotationError::Invalid(name.to_string());
+        let mut chars = name.chars();
+        let file = chars.next().ok_or_else(invalid)?;
+        let rank = chars.next().ok_or_else(invalid)?;
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+            return Err(invalid());
+        }
+        Ok(Square::new(rank as usize - '1' as usize, file as usize - 'a' as usize))
+    }
+}
+
+/// Long algebraic notation as spoken by UCI: `e2e4`, `e7e8q`, castling as the king's move `e1g1`.
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.from, self.to)?;
+        if let Some(promotion) = self.promotion {
+            let letter = piece_letter(promotion).unwrap_or('q').to_ascii_lowercase();
+            write!(f, "{letter}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the legal move in `position` written in UCI long algebraic notation.
+pub fn parse_uci(position: &Position, text: &str) -> Result<Move, NotationError> {
+    let invalid = || NotationError::Invalid(text.to_string());
+    if !(4..=5).contains(&text.len()) || !text.is_ascii() {
+        return Err(invalid());
+    }
+
+    let from: Square = text[0..2].parse().map_err(|_| invalid())?;
+    let to: Square = text[2..4].parse().map_err(|_| invalid())?;
+    let promotion = match text[4..].chars().next() {
+        None => None,
+        Some('q') => Some(PieceType::Queen),
+        Some('r') => Some(PieceType::Rook),
+        Some('b') => Some(PieceType::Bishop),
+        Some('n') => Some(PieceType::Knight),
+        Some(_) => return Err(invalid()),
+    };
+
+    position.legal_moves().into_iter()
+        .find(|mv| mv.from == from && mv.to == to && mv.promotion == promotion)
+        .ok_or_else(|| NotationError::Illegal(text.to_string()))
+}
+
+/// SAN letter of a piece; pawns have none.
+pub fn piece_letter(piece_type: PieceType) -> Option<char> {
+    match piece_type {
+        PieceType::Knight => Some('N'),
+        PieceType::Bishop => Some('B'),
+        PieceType::Rook => Some('R'),
+        PieceType::Queen => Some('Q'),
+        PieceType::King => Some('K'),
+        PieceType::Pawn | PieceType::Empty => None,
+    }
+}
+
+/// Standard Algebraic Notation for `mv`, which must be legal in `position`.
+/// Includes disambiguation and the `+`/`#` suffix.
+pub fn move_to_san(position: &Position, mv: Move) -> String {
+    let mut san = String::new();
+    let piece = position.piece_at(mv.from).expect("move_to_san: no piece on the from square");
+
+    if let Some((rook_from, _)) = mv.castling_rook() {
+        san.push_str(if rook_from.col() == 7 { "O-O" } else { "O-O-O" });
+    } else {
+        let is_capture = mv.kind == MoveKind::EnPassant || position.piece_at(mv.to).is_some();
+
+        match piece_letter(piece.piece_type) {
+            Some(letter) => {
+                san.push(letter);
+                san.push_str(&disambiguation(position, mv, piece.piece_type));
+            }
+            None if is_capture => san.push((b'a' + mv.from.col() as u8) as char),
+            None => {}
+        }
+
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&mv.to.to_string());
+
+        if let Some(promotion) = mv.promotion {
+            san.push('=');
+            san.extend(piece_letter(promotion));
+        }
+    }
+
+    let mut after = position.clone();
+    after.make_move(mv);
+    if after.is_in_check() {
+        san.push(if after.legal_moves().is_empty() { '#' } else { '+' });
+    }
+
+    san
+}
+
+/// File, rank or both of the origin square, when another piece of the same type could reach the same square.
+fn disambiguation(position: &Position, mv: Move, piece_type: PieceType) -> String {
+    let rivals: Vec<Square> = position.legal_moves().into_iter()
+        .filter(|other| other.to == mv.to && other.from != mv.from)
+        .filter(|other| position.piece_at(other.from).is_some_and(|piece| piece.piece_type == piece_type))
+        .map(|other| other.from)
+        .collect();
+
+    if rivals.is_empty() {
+        return String::new();
+    }
+
+    let name = mv.from.to_string();
+    if rivals.iter().all(|rival| rival.col() != mv.from.col()) {
+        name[..1].to_string()
+    } else if rivals.iter().all(|rival| rival.row() != mv.from.row()) {
+        name[1..].to_string()
+    } else {
+        name
+    }
+}
+
+/// Finds the legal move in `position` written as `san`. Check and annotation
+/// suffixes (`+`, `#`, `!`, `?`) are ignored, `0-0` is accepted for castling and
+/// the `=` before a promotion piece is optional.
+pub fn parse_san(position: &Position, san: &str) -> Result<Move, NotationError> {
+    let invalid = || NotationError::Invalid(san.to_string());
+    let text = san.trim_end_matches(['+', '#', '!', '?']);
+
+    let legal_moves = position.legal_moves();
+    let pick = |candidates: Vec<Move>| match candidates.len() {
+        0 => Err(NotationError::Illegal(san.to_string())),
+        1 => Ok(candidates[0]),
+        _ => Err(NotationError::Ambiguous(san.to_string())),
+    };
+
+    // Castling
+    let castle_side = match text {
+        "O-O" | "0-0" => Some(7),
+        "O-O-O" | "0-0-0" => Some(0),
+        _ => None,
+    };
+    if let Some(rook_col) = castle_side {
+        return pick(legal_moves.into_iter()
+            .filter(|mv| mv.castling_rook().is_some_and(|(rook_from, _)| rook_from.col() == rook_col))
+            .collect());
+    }
+
+    let mut chars: Vec<char> = text.chars().collect();
+
+    let piece_type = match chars.first() {
+        Some('N') => PieceType::Knight,
+        Some('B') => PieceType::Bishop,
+        Some('R') => PieceType::Rook,
+        Some('Q') => PieceType::Queen,
+        Some('K') => PieceType::King,
+        Some('a'..='h') => PieceType::Pawn,
+        _ => return Err(invalid()),
+    };
+    if piece_type != PieceType::Pawn {
+        chars.remove(0);
+    }
+
+    // Promotion suffix, with or without '='
+    let mut promotion = None;
+    if let Some(&last) = chars.last() {
+        let promoted = match last {
+            'Q' => Some(PieceType::Queen),
+            'R' => Some(PieceType::Rook),
+            'B' => Some(PieceType::Bishop),
+            'N' => Some(PieceType::Knight),
+            _ => None,
+        };
+        if promoted.is_some() {
+            if piece_type != PieceType::Pawn {
+                return Err(invalid());
+            }
+            promotion = promoted;
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+    }
+
+    if chars.len() < 2 {
+        return Err(invalid());
+    }
+    let destination: String = chars[chars.len() - 2..].iter().collect();
+    let to: Square = destination.parse().map_err(|_| invalid())?;
+
+    // Whatever is left between the piece letter and the destination: disambiguation and 'x'
+    let mut from_col = None;
+    let mut from_row = None;
+    for &symbol in &chars[..chars.len() - 2] {
+        match symbol {
+            'a'..='h' => from_col = Some(symbol as usize - 'a' as usize),
+            '1'..='8' => from_row = Some(symbol as usize - '1' as usize),
+            'x' | ':' | '-' => {}
+            _ => return Err(invalid()),
+        }
+    }
+
+    pick(legal_moves.into_iter()
+        .filter(|mv| mv.to == to && mv.promotion == promotion && mv.kind != MoveKind::Castle)
+        .filter(|mv| position.piece_at(mv.from).is_some_and(|piece| piece.piece_type == piece_type))
+        .filter(|mv| from_col.is_none_or(|col| mv.from.col() == col))
+        .filter(|mv| from_row.is_none_or(|row| mv.from.row() == row))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(fen: &str) -> Position {
+        Position::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn reads_and_writes_square_names() {
+        assert_eq!("a1".parse::<Square>(), Ok(Square::new(0, 0)));
+        assert_eq!("h8".parse::<Square>(), Ok(Square::new(7, 7)));
+        assert_eq!(Square::new(3, 4).to_string(), "e4");
+        for bad in ["", "e", "i1", "a9", "a0", "e44"] {
+            assert!(bad.parse::<Square>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn disambiguates_by_file() {
+        let position = position("4k3/8/8/8/8/8/8/1R3RK1 w - - 0 1");
+        let mv = parse_san(&position, "Rbd1").unwrap();
+        assert_eq!((mv.from, mv.to), (Square::new(0, 1), Square::new(0, 3)));
+        assert_eq!(parse_san(&position, "Rfd1").unwrap().from, Square::new(0, 5));
+        assert_eq!(move_to_san(&position, mv), "Rbd1");
+        assert!(matches!(parse_san(&position, "Rd1"), Err(NotationError::Ambiguous(_))));
+    }
+
+    #[test]
+    fn disambiguates_by_rank() {
+        let position = position("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1");
+        let mv = parse_san(&position, "R1a3").unwrap();
+        assert_eq!((mv.from, mv.to), (Square::new(0, 0), Square::new(2, 0)));
+        assert_eq!(parse_san(&position, "R5a3").unwrap().from, Square::new(4, 0));
+        assert_eq!(move_to_san(&position, mv), "R1a3");
+        assert!(matches!(parse_san(&position, "Ra3"), Err(NotationError::Ambiguous(_))));
+    }
+
+    #[test]
+    fn reads_promotions_with_and_without_equals() {
+        let position = position("7k/4P3/8/8/8/8/8/4K3 w - - 0 1");
+        let mv = parse_san(&position, "e8=Q+").unwrap();
+        assert_eq!((mv.to, mv.promotion), (Square::new(7, 4), Some(PieceType::Queen)));
+        assert_eq!(move_to_san(&position, mv), "e8=Q+");
+        assert_eq!(parse_san(&position, "e8N").unwrap().promotion, Some(PieceType::Knight));
+        assert!(matches!(parse_san(&position, "e8"), Err(NotationError::Illegal(_))));
+    }
+
+    #[test]
+    fn reads_castling_and_captures() {
+        let position = position("r3k2r/8/8/3p4/4P3/8/8/R3K2R w KQkq - 0 1");
+        assert_eq!(parse_san(&position, "O-O").unwrap().to, Square::new(0, 6));
+        assert_eq!(parse_san(&position, "0-0-0").unwrap().to, Square::new(0, 2));
+        let capture = parse_san(&position, "exd5").unwrap();
+        assert_eq!(move_to_san(&position, capture), "exd5");
+        assert_eq!(parse_uci(&position, "e4d5"), Ok(capture));
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        let start = Position::starting();
+        for bad in ["", "Z", "Zf3", "e9", "Nxx", "Ke", "é4", "Be2=Q", "N?f3"] {
+            assert!(matches!(parse_san(&start, bad), Err(NotationError::Invalid(_))), "{bad}");
+        }
+        for illegal in ["e5", "Nd2", "Ke2", "O-O", "exd3"] {
+            assert!(matches!(parse_san(&start, illegal), Err(NotationError::Illegal(_))), "{illegal}");
+        }
+        for bad in ["", "e2", "e2e9", "e2e4x", "e7e8k", "e2e5"] {
+            assert!(parse_uci(&start, bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Perft: counts the leaf nodes of the legal move tree to a fixed depth, the
+//! standard way of checking a move generator against known results.
+//!
+//! Run as `chess_bevy --perft <depth> [--divide] [fen]`.
+
+use std::time::Instant;
+
+use crate::rules::*;
+
+/// Number of move sequences `depth` plies long from `position`.
+pub fn perft(position: &mut Position, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = position.legal_moves();
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for mv in moves {
+        position.make_move(mv);
+        nodes += perft(position, depth - 1);
+        position.unmake_move();
+    }
+    nodes
+}
+
+/// Perft split by the first move, to narrow down which move a wrong count comes from.
+pub fn divide(position: &mut Position, depth: u32) -> Vec<(Move, u64)> {
+    let mut counts = Vec::new();
+    for mv in position.legal_moves() {
+        position.make_move(mv);
+        counts.push((mv, perft(position, depth.saturating_sub(1))));
+        position.unmake_move();
+    }
+    counts
+}
+
+/// Command line entry point; `arguments` are the ones after `--perft`.
+pub fn run(arguments: &[String]) {
+    let usage = "usage: chess_bevy --perft <depth> [--divide] [fen]";
+
+    let Some(depth) = arguments.first().and_then(|depth| depth.parse::<u32>().ok()) else {
+        eprintln!("{usage}");
+        return;
+    };
+    let show_divide = arguments.iter().any(|argument| argument == "--divide");
+    let fen: Vec<&str> = arguments[1..].iter()
+        .filter(|argument| *argument != "--divide")
+        .map(String::as_str)
+        .collect();
+
+    let mut position = if fen.is_empty() {
+        Position::starting()
+    } else {
+        match Position::from_fen(&fen.join(" ")) {
+            Ok(position) => position,
+            Err(error) => {
+                eprintln!("invalid FEN: {error}");
+                return;
+            }
+        }
+    };
+
+    let start = Instant::now();
+    let nodes = if show_divide {
+        let counts = divide(&mut position, depth);
+        for (mv, count) in &counts {
+            println!("{mv}: {count}");
+        }
+        println!();
+        counts.iter().map(|(_, count)| count).sum()
+    } else {
+        perft(&mut position, depth)
+    };
+    let elapsed = start.elapsed();
+
+    println!("Nodes searched: {nodes}");
+    println!("Time: {:.3}s ({:.0} nodes/s)", elapsed.as_secs_f64(), nodes as f64 / elapsed.as_secs_f64().max(1e-9));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::STARTING_FEN;
+
+    // Reference positions and counts from the Chess Programming Wiki perft results page
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+    const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+    const POSITION_4_MIRRORED: &str = "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+    const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+    const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+    fn assert_perft(fen: &str, expected: &[u64]) {
+        let mut position = Position::from_fen(fen).unwrap();
+        for (depth, &nodes) in expected.iter().enumerate() {
+            let depth = depth as u32 + 1;
+            assert_eq!(perft(&mut position, depth), nodes, "perft({depth}) of {fen}");
+        }
+        // make_move/unmake_move must leave the position exactly as it was
+        assert_eq!(position.to_fen(), Position::from_fen(fen).unwrap().to_fen());
+    }
+
+    #[test]
+    fn starting_position() {
+        assert_perft(STARTING_FEN, &[20, 400, 8_902, 197_281]);
+    }
+
+    #[test]
+    fn kiwipete() {
+        assert_perft(KIWIPETE, &[48, 2_039, 97_862]);
+    }
+
+    #[test]
+    fn position_3_en_passant_and_checks() {
+        assert_perft(POSITION_3, &[14, 191, 2_812, 43_238]);
+    }
+
+    #[test]
+    fn position_4_promotions_and_castling() {
+        assert_perft(POSITION_4, &[6, 264, 9_467]);
+        assert_perft(POSITION_4_MIRRORED, &[6, 264, 9_467]);
+    }
+
+    #[test]
+    fn position_5() {
+        assert_perft(POSITION_5, &[44, 1_486, 62_379]);
+    }
+
+    #[test]
+    fn position_6() {
+        assert_perft(POSITION_6, &[46, 2_079, 89_890]);
+    }
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        let mut position = Position::from_fen(KIWIPETE).unwrap();
+        let counts = divide(&mut position, 3);
+        assert_eq!(counts.len(), 48);
+        assert_eq!(counts.iter().map(|(_, count)| count).sum::<u64>(), 97_862);
+
+        // Queen-side castling is one of Kiwipete's first moves
+        let castle = counts.iter().find(|(mv, _)| mv.to_string() == "e1c1").unwrap();
+        assert_eq!(castle.1, 1_887);
+    }
+
+    #[test]
+    #[ignore = "slow, run with --ignored"]
+    fn deep_counts() {
+        assert_perft(STARTING_FEN, &[20, 400, 8_902, 197_281, 4_865_609]);
+        assert_perft(KIWIPETE, &[48, 2_039, 97_862, 4_085_603]);
+        assert_perft(POSITION_3, &[14, 191, 2_812, 43_238, 674_624]);
+        assert_perft(POSITION_4, &[6, 264, 9_467, 422_333]);
+        assert_perft(POSITION_5, &[44, 1_486, 62_379, 2_103_487]);
+        assert_perft(POSITION_6, &[46, 2_079, 89_890, 3_894_594]);
+    }
+}
@@ -0,0 +1,481 @@
+//! Portable Game Notation.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fen::STARTING_FEN;
+use crate::notation::{parse_san, NotationError};
+use crate::rules::*;
+
+/// Longest movetext line written, as recommended by the PGN standard.
+const MAX_LINE_LENGTH: usize = 79;
+
+/// A single game: its tag pairs, moves in SAN and result token.
+#[derive(Clone, Debug, Default)]
+pub struct PgnGame {
+    pub headers: Vec<(String, String)>,
+    pub moves: Vec<String>,
+    pub comments: Vec<(usize, String)>, // number of moves played before the comment, text
+    pub result: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnError {
+    Syntax(String),
+    IllegalMove { ply: usize, error: NotationError },
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::Syntax(message) => write!(f, "syntax error: {message}"),
+            PgnError::IllegalMove { ply, error } => write!(f, "move {}: {error}", ply / 2 + 1),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl PgnGame {
+    /// A game with the Seven Tag Roster filled in for today, plus `SetUp`/`FEN`
+    /// when it did not start from the standard position.
+    pub fn new(start_fen: &str, moves: Vec<String>, outcome: Option<GameOutcome>) -> Self {
+        let result = result_token(outcome).to_string();
+        let mut headers = vec![
+            ("Event".to_string(), "Casual game".to_string()),
+            ("Site".to_string(), "Bevy Chess".to_string()),
+            ("Date".to_string(), today()),
+            ("Round".to_string(), "-".to_string()),
+            ("White".to_string(), "?".to_string()),
+            ("Black".to_string(), "?".to_string()),
+            ("Result".to_string(), result.clone()),
+        ];
+        if start_fen != STARTING_FEN {
+            headers.push(("SetUp".to_string(), "1".to_string()));
+            headers.push(("FEN".to_string(), start_fen.to_string()));
+        }
+
+        Self { headers, moves, comments: Vec::new(), result }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Position the game starts from, taken from the `FEN` tag when there is one.
+    pub fn start_position(&self) -> Position {
+        self.header("FEN")
+            .and_then(|fen| Position::from_fen(fen).ok())
+            .unwrap_or_else(Position::starting)
+    }
+
+    /// Plays through the movetext from the start position and returns the moves,
+    /// failing on the first SAN that is not legal.
+    pub fn replay(&self) -> Result<Vec<Move>, PgnError> {
+        let mut position = self.start_position();
+        let mut moves = Vec::with_capacity(self.moves.len());
+        for (ply, san) in self.moves.iter().enumerate() {
+            let mv = parse_san(&position, san).map_err(|error| PgnError::IllegalMove { ply, error })?;
+            position.make_move(mv);
+            moves.push(mv);
+        }
+        Ok(moves)
+    }
+
+    /// The comment written after `ply` moves, if there is one.
+    pub fn comment_at(&self, ply: usize) -> Option<&str> {
+        self.comments.iter().find(|(at, _)| *at == ply).map(|(_, text)| text.as_str())
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut pgn = String::new();
+        for (name, value) in &self.headers {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            pgn.push_str(&format!("[{name} \"{value}\"]\n"));
+        }
+        pgn.push('\n');
+
+        let start = self.start_position();
+        let mut move_number = start.fullmove_number();
+        let mut white_to_move = start.side_to_move() == PieceColor::White;
+
+        let mut tokens = Vec::with_capacity(self.moves.len() * 3 / 2 + 1);
+        if let Some(comment) = self.comment_at(0) {
+            tokens.push(format!("{{{comment}}}"));
+        }
+        for (index, san) in self.moves.iter().enumerate() {
+            if white_to_move {
+                tokens.push(format!("{move_number}."));
+            } else if index == 0 || self.comment_at(index).is_some() {
+                tokens.push(format!("{move_number}..."));
+            }
+            tokens.push(san.clone());
+            if let Some(comment) = self.comment_at(index + 1) {
+                tokens.push(format!("{{{comment}}}"));
+            }
+
+            if !white_to_move {
+                move_number += 1;
+            }
+            white_to_move = !white_to_move;
+        }
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push_str("\n\n");
+        pgn
+    }
+}
+
+/// Parses every game in `text`. Comments are kept and NAGs skipped. Variations are
+/// dropped along with the comments inside them, so only the main line of each game
+/// is returned.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut games = Vec::new();
+    let mut game = PgnGame::default();
+    let mut in_movetext = false;
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut index = 0;
+    let mut line_start = true;
+
+    while index < chars.len() {
+        let symbol = chars[index];
+
+        // Escape mechanism: lines starting with '%' are ignored
+        if line_start && symbol == '%' {
+            while index < chars.len() && chars[index] != '\n' {
+                index += 1;
+            }
+            continue;
+        }
+        line_start = symbol == '\n';
+
+        match symbol {
+            _ if symbol.is_whitespace() => index += 1,
+            '[' => {
+                // A tag after movetext starts the next game
+                if in_movetext {
+                    finish_game(&mut games, &mut game);
+                    in_movetext = false;
+                }
+                let end = find_tag_end(&chars, index).ok_or_else(|| PgnError::Syntax("unterminated tag pair".to_string()))?;
+                let tag: String = chars[index + 1..end].iter().collect();
+                game.headers.push(parse_tag(&tag)?);
+                index = end + 1;
+            }
+            '{' => {
+                let end = chars[index..].iter().position(|&c| c == '}')
+                    .ok_or_else(|| PgnError::Syntax("unterminated comment".to_string()))?;
+                let comment: String = chars[index + 1..index + end].iter().collect();
+                let comment = comment.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !comment.is_empty() {
+                    game.comments.push((game.moves.len(), comment));
+                }
+                in_movetext = true;
+                index += end + 1;
+            }
+            ';' => {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+            }
+            '(' => {
+                index = skip_variation(&chars, index)?;
+                in_movetext = true;
+            }
+            ')' => return Err(PgnError::Syntax("unbalanced ')'".to_string())),
+            '$' => {
+                index += 1;
+                while index < chars.len() && chars[index].is_ascii_digit() {
+                    index += 1;
+                }
+            }
+            _ => {
+                let start = index;
+                while index < chars.len() && !chars[index].is_whitespace() && !"[]{}();$".contains(chars[index]) {
+                    index += 1;
+                }
+                let token: String = chars[start..index].iter().collect();
+                in_movetext = true;
+
+                if matches!(token.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+                    game.result = token;
+                    finish_game(&mut games, &mut game);
+                    in_movetext = false;
+                    continue;
+                }
+
+                // Move numbers may be glued to the move, as in "12.e4" or "12...Nf6". Digits not
+                // followed by a dot are part of the move: "0-0" castles
+                let number = token.trim_start_matches(|c: char| c.is_ascii_digit());
+                let san = if number.starts_with('.') { number.trim_start_matches('.') } else { token.as_str() };
+                let san = san.trim_end_matches(['!', '?']);
+                if !san.is_empty() {
+                    game.moves.push(san.to_string());
+                }
+            }
+        }
+    }
+
+    finish_game(&mut games, &mut game);
+    Ok(games)
+}
+
+fn finish_game(games: &mut Vec<PgnGame>, game: &mut PgnGame) {
+    let mut game = std::mem::take(game);
+    if game.headers.is_empty() && game.moves.is_empty() {
+        return;
+    }
+
+    if game.result.is_empty() {
+        game.result = game.header("Result").unwrap_or("*").to_string();
+    }
+    games.push(game);
+}
+
+/// Index of the ']' closing the tag pair opened at `start`, skipping over the quoted value.
+fn find_tag_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut in_string = false;
+    let mut index = start + 1;
+    while index < chars.len() {
+        match chars[index] {
+            '\\' if in_string => index += 1,
+            '"' => in_string = !in_string,
+            ']' if !in_string => return Some(index),
+            _ => {}
+        }
+        index += 1;
+    }
+    None
+}
+
+fn parse_tag(tag: &str) -> Result<(String, String), PgnError> {
+    let tag = tag.trim();
+    let (name, value) = tag.split_once(char::is_whitespace)
+        .ok_or_else(|| PgnError::Syntax(format!("malformed tag pair [{tag}]")))?;
+    let value = value.trim();
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(PgnError::Syntax(format!("malformed tag pair [{tag}]")));
+    }
+    let value = value[1..value.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\");
+    Ok((name.to_string(), value))
+}
+
+/// Index just past the ')' closing the variation opened at `start`, including nested ones.
+fn skip_variation(chars: &[char], start: usize) -> Result<usize, PgnError> {
+    let mut depth = 0;
+    let mut index = start;
+    while index < chars.len() {
+        match chars[index] {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(index + 1);
+                }
+            }
+            '{' => {
+                let end = chars[index..].iter().position(|&c| c == '}')
+                    .ok_or_else(|| PgnError::Syntax("unterminated comment".to_string()))?;
+                index += end;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    Err(PgnError::Syntax("unterminated variation".to_string()))
+}
+
+/// Writes `game` to a new timestamped `.pgn` file in `directory`, creating it if needed.
+/// Files are never overwritten: a second export in the same second gets a counter suffix.
+pub fn write_pgn(directory: &Path, game: &PgnGame) -> io::Result<PathBuf> {
+    fs::create_dir_all(directory)?;
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or_default();
+    for attempt in 0u32.. {
+        let name = match attempt {
+            0 => format!("game_{timestamp}.pgn"),
+            _ => format!("game_{timestamp}_{attempt}.pgn"),
+        };
+        let path = directory.join(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(game.to_pgn().as_bytes())?;
+                return Ok(path);
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    unreachable!("ran out of file names")
+}
+
+pub fn result_token(outcome: Option<GameOutcome>) -> &'static str {
+    match outcome {
+        Some(GameOutcome::Checkmate { winner: PieceColor::White }) => "1-0",
+        Some(GameOutcome::Checkmate { winner: PieceColor::Black }) => "0-1",
+        Some(GameOutcome::Stalemate | GameOutcome::Draw(_)) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+/// Today's date in the PGN `YYYY.MM.DD` format (UTC).
+fn today() -> String {
+    let Ok(elapsed) = SystemTime::now().duration_since(UNIX_EPOCH) else {
+        return "????.??.??".to_string();
+    };
+
+    // Days since 1970-01-01 to a civil date, Howard Hinnant's algorithm
+    let days = (elapsed.as_secs() / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_one(text: &str) -> PgnGame {
+        let mut games = parse_pgn(text).unwrap();
+        assert_eq!(games.len(), 1);
+        games.remove(0)
+    }
+
+    #[test]
+    fn reads_headers_with_escaped_quotes() {
+        let game = parse_one(r#"[Event "Club \"Open\""]
+[White "Smith, A."]
+[Result "1-0"]
+
+1. e4 e5 1-0"#);
+        assert_eq!(game.header("Event"), Some(r#"Club "Open""#));
+        assert_eq!(game.header("White"), Some("Smith, A."));
+        assert_eq!(game.header("Black"), None);
+        assert_eq!(game.result, "1-0");
+    }
+
+    #[test]
+    fn keeps_comments_with_the_move_before_them() {
+        let game = parse_one("{Opening} 1. e4 {best by test}\n e5 ; rest of line ignored\n2. Nf3 *");
+        assert_eq!(game.moves, ["e4", "e5", "Nf3"]);
+        assert_eq!(game.comment_at(0), Some("Opening"));
+        assert_eq!(game.comment_at(1), Some("best by test"));
+        assert_eq!(game.comment_at(2), None);
+        assert_eq!(game.result, "*");
+    }
+
+    #[test]
+    fn skips_nags_and_annotation_marks() {
+        let game = parse_one("1. e4! $1 e5?! $6 2. Nf3 $14 Nc6?? *");
+        assert_eq!(game.moves, ["e4", "e5", "Nf3", "Nc6"]);
+    }
+
+    #[test]
+    fn skips_nested_variations() {
+        let game = parse_one("1. e4 (1. d4 d5 (1... Nf6 2. c4 {a comment with ) inside}) 2. c4) 1... c5 (1... e5 2. Nf3) 2. Nf3 *");
+        assert_eq!(game.moves, ["e4", "c5", "Nf3"]);
+        assert_eq!(game.replay().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn splits_a_file_into_games() {
+        let games = parse_pgn(r#"[Event "First"]
+
+1. e4 e5 1-0
+
+[Event "Second"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]
+
+1. e4 Kd7 1/2-1/2
+"#).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!((games[0].header("Event"), games[0].result.as_str()), (Some("First"), "1-0"));
+        assert_eq!((games[1].header("Event"), games[1].result.as_str()), (Some("Second"), "1/2-1/2"));
+        assert_eq!(games[1].start_position().to_fen(), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(games[1].replay().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reads_move_numbers_glued_to_moves() {
+        let game = parse_one("1.e4 Nf6 2.e5 2...Nd5 3.d4 d6 *");
+        assert_eq!(game.moves, ["e4", "Nf6", "e5", "Nd5", "d4", "d6"]);
+
+        let game = parse_one(r#"[FEN "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 12"]
+
+12...Nf6 13.Nc3 *"#);
+        assert_eq!(game.moves, ["Nf6", "Nc3"]);
+        assert!(game.replay().is_ok());
+    }
+
+    #[test]
+    fn reads_castling_written_with_zeros() {
+        let game = parse_one("1. e4 e5 2. Nf3 Nc6 3. Bc4 d6 4. 0-0 Be6 5. d3 Qd7 6. Nc3 0-0-0 *");
+        assert_eq!(game.moves[6], "0-0");
+        assert_eq!(game.moves[11], "0-0-0");
+        assert_eq!(game.replay().unwrap()[11].kind, MoveKind::Castle);
+
+        let game = parse_one("1.e4 e5 2.Nf3 Nc6 3.Bc4 Nf6 4.0-0 Bc5 5.d3 0-0 *");
+        assert_eq!((game.moves[6].as_str(), game.moves[9].as_str()), ("0-0", "0-0"));
+        assert!(game.replay().is_ok());
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        for text in ["[Event \"Unterminated", "[Event]", "1. e4 {no end", "1. e4 (1. d4 d5", "1. e4 ) e5"] {
+            assert!(matches!(parse_pgn(text), Err(PgnError::Syntax(_))), "{text}");
+        }
+
+        let game = parse_one("1. e4 e5 2. Ke3 *");
+        assert!(matches!(game.replay(), Err(PgnError::IllegalMove { ply: 2, .. })));
+        let game = parse_one("1. e4 Qxe4 *");
+        assert!(matches!(game.replay(), Err(PgnError::IllegalMove { ply: 1, .. })));
+    }
+
+    #[test]
+    fn written_games_read_back() {
+        let mut game = PgnGame::new(STARTING_FEN, ["e4", "e5", "Nf3", "Nc6", "Bb5"].map(String::from).to_vec(), None);
+        game.comments.push((3, "the main line".to_string()));
+        let read = parse_one(&game.to_pgn());
+        assert_eq!(read.moves, game.moves);
+        assert_eq!(read.comments, game.comments);
+        assert_eq!(read.headers, game.headers);
+    }
+
+    #[test]
+    fn exports_in_the_same_second_get_their_own_files() {
+        let scratch = crate::test_files::TempDir::new("pgn");
+        let directory = scratch.path().join("exports"); // created by the first export
+        let game = PgnGame::new(STARTING_FEN, vec!["e4".to_string()], None);
+
+        let paths: Vec<PathBuf> = (0..3).map(|_| write_pgn(&directory, &game).unwrap()).collect();
+        assert!(paths[0] != paths[1] && paths[1] != paths[2] && paths[0] != paths[2]);
+        for path in &paths {
+            assert_eq!(fs::read_to_string(path).unwrap(), game.to_pgn());
+        }
+    }
+}
@@ -1,31 +1,18 @@
 use bevy::prelude::*;
 
-use crate::config::{TILE_SIZE, PIECE_SCALE};
+use crate::config::{PIECE_RETURN_TIME, PIECE_SCALE};
 use crate::board::*;
+use crate::resource_allocation::GameState;
+use crate::rules::{Position, Square};
 
 
-#[derive(Debug, PartialEq)]
-pub enum PieceType {
-    Pawn,
-    Rook,
-    Knight,
-    Bishop,
-    Queen,
-    King,
-    Empty,
-}
-
-#[derive(Debug, PartialEq)]
-pub enum PieceColor {
-    White,
-    Black,
-}
+pub use crate::rules::{PieceColor, PieceType};
 
 #[derive(Component, Debug)]
 pub struct Piece {
     pub piece_type: PieceType,
     pub piece_color: PieceColor,
-    pub position: (usize, usize),
+    pub position: Square,
     pub has_moved: bool,
     pub is_captured: bool,
 }
@@ -35,468 +22,145 @@ impl Default for Piece {
         Self {
             piece_type: PieceType::Empty,
             piece_color: PieceColor::White, // Choose a default color
-            position: (0, 0),
+            position: Square::new(0, 0),
             has_moved: false,
             is_captured: false,
         }
     }
 }
 
+/// The pieces as the move code sees them: where they are drawn, what they are and whether they are shown.
+pub type PieceQuery<'w, 's> = Query<'w, 's, (&'static mut Transform, &'static mut Piece, &'static mut Visibility), With<Piece>>;
 
-pub fn create_pieces(
-    mut commands: Commands,
-    mut board_query: Query<(Entity, &mut ChessBoard), With<ChessBoard>>,
-    asset_server: Res<AssetServer>
-){
-    if let Ok((board_entity, mut chess_board)) = board_query.get_single_mut() {
-
-        let white_pawn: Handle<Image> = asset_server.load("pieces/w_pawn.png");
-        let white_rook: Handle<Image> = asset_server.load("pieces/w_rook.png");
-        let white_knight: Handle<Image> = asset_server.load("pieces/w_knight.png");
-        let white_bishop: Handle<Image> = asset_server.load("pieces/w_bishop.png");
-        let white_queen: Handle<Image> = asset_server.load("pieces/w_queen.png");
-        let white_king: Handle<Image> = asset_server.load("pieces/w_king.png");
-        
-
-        let black_pawn: Handle<Image> = asset_server.load("pieces/b_pawn.png");
-        let black_rook: Handle<Image> = asset_server.load("pieces/b_rook.png");
-        let black_knight: Handle<Image> = asset_server.load("pieces/b_knight.png");
-        let black_bishop: Handle<Image> = asset_server.load("pieces/b_bishop.png");
-        let black_queen: Handle<Image> = asset_server.load("pieces/b_queen.png");
-        let black_king: Handle<Image> = asset_server.load("pieces/b_king.png");
-        
-
-        let tile_center = TILE_SIZE / 2.0;
-        let white_pos_y = tile_center;
-        let white_pawn_pos_y = TILE_SIZE + (tile_center);
-
-        let black_pos_y = 7.0 * TILE_SIZE + (tile_center);
-        let black_pawn_pos_y = 6.0 * TILE_SIZE + (tile_center);
-
-        let piece_scale = Vec3::new(PIECE_SCALE, PIECE_SCALE, 1.0);
-        //// White pieces ////
-        // Pawns
-        {
-        for i in 0..8 {
-            let pawn = commands.spawn((
-                Sprite {
-                    image: white_pawn.clone(),
-                    ..default()
-                },
-                Transform{
-                    translation: Vec3::new(i as f32 * TILE_SIZE + (TILE_SIZE / 2.0), white_pawn_pos_y, 1.0),
-                    scale: piece_scale,
-                    ..default()
-                },
-                Piece {
-                    piece_type: PieceType::Pawn,
-                    piece_color: PieceColor::White,
-                    position: (2, i+1),
-                    ..default()
-                } 
-            )).set_parent(board_entity)
-            .id();
-            chess_board.pieces[1][i as usize] = Some(pawn);
-        }
-        }
-           
-        // Rooks
-        {
-        let rook1 = commands.spawn((
-            Sprite {
-                image: white_rook.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(white_pos_y, white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Rook,
-                piece_color: PieceColor::White,
-                position: (1, 1),
-                ..default()
-
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][0] = Some(rook1);
-
-        let rook2 = commands.spawn((
-            Sprite {
-                image: white_rook.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(7.0 * TILE_SIZE + (white_pos_y), white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Rook,
-                piece_color: PieceColor::White,
-                position: (1, 8),
-                ..default()
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][7] = Some(rook2);
-        }
-
-        // Knights
-        {
-        let knight1 = commands.spawn((
-            Sprite {
-                image: white_knight.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(TILE_SIZE + white_pos_y, white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Knight,
-                piece_color: PieceColor::White,
-                position: (1, 2),
-                ..default()
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][1] = Some(knight1);
-
-        let knight2 = commands.spawn((
-            Sprite {
-                image: white_knight.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(6.0 * TILE_SIZE + white_pos_y, white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Knight,
-                piece_color: PieceColor::White,
-                position: (1, 7),
-                ..default()
-
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][6] = Some(knight2);
-        }
-
-        // Bishops
-        {
-        let bishop1 = commands.spawn((
-            Sprite {
-                image: white_bishop.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(2.0 * TILE_SIZE + white_pos_y, white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Bishop,
-                piece_color: PieceColor::White,
-                position: (1, 3),
-                ..default()
-
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][2] = Some(bishop1);
-
-        let bishop2 = commands.spawn((
-            Sprite {
-                image: white_bishop.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(5.0 * TILE_SIZE + white_pos_y, white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Bishop,
-                piece_color: PieceColor::White,
-                position: (1, 6),
-                ..default()
-
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][5] = Some(bishop2);
-
-        }
-        
-        // Queen
-        {
-        let queen = commands.spawn((
-            Sprite {
-                image: white_queen.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(3.0 * TILE_SIZE + white_pos_y, white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Queen,
-                piece_color: PieceColor::White,
-                position: (1, 4),
-                ..default()
-
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][3] = Some(queen);
-        }
-        
-        // King
-        {
-        let king = commands.spawn((
-            Sprite {
-                image: white_king.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(4.0 * TILE_SIZE + white_pos_y, white_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::King,
-                piece_color: PieceColor::White,
-                position: (1, 5),
-                ..default()
-
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[0][4] = Some(king);
-        }
-        
-        
-        //// Black pieces ////
-        // Pawns
-        {     
-        for i in 0..8 {
-            let pawn = commands.spawn((
-                Sprite {
-                    image: black_pawn.clone(),
-                    ..default()
-                },
-                Transform{
-                    translation: Vec3::new(i as f32 * TILE_SIZE + (TILE_SIZE / 2.0), black_pawn_pos_y, 1.0),
-                    scale: piece_scale,
-                    ..default()
-                },
-                Piece {
-                    piece_type: PieceType::Pawn,
-                    piece_color: PieceColor::Black,
-                    position: (7, i+1),
-                    ..default()
-
-                } 
-            )).set_parent(board_entity)
-            .id();
-            chess_board.pieces[6][i as usize] = Some(pawn);
-        }
-        }      
-
-        // Rooks
-        {
-        let rook1 = commands.spawn((
-            Sprite {
-                image: black_rook.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(tile_center, black_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Rook,
-                piece_color: PieceColor::Black,
-                position: (7, 1),
-                ..default()
-
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[7][0] = Some(rook1);
+/// A piece sliding back to its square after an illegal drop.
+#[derive(Component)]
+pub struct ReturningPiece {
+    pub start: Vec2, // where it was let go, board space
+    pub elapsed: f32,
+}
 
-        let rook2 = commands.spawn((
-            Sprite {
-                image: black_rook.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(7.0 * TILE_SIZE + (tile_center), black_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Rook,
-                piece_color: PieceColor::Black,
-                position: (7, 8),
-                ..default()
+/// Sprite handles for every piece, so pieces can change their image after spawning (promotion).
+#[derive(Resource)]
+pub struct PieceSprites {
+    pub white: [Handle<Image>; 6],
+    pub black: [Handle<Image>; 6],
+}
 
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[7][7] = Some(rook2);
+impl PieceSprites {
+    pub fn get(&self, piece_type: PieceType, piece_color: PieceColor) -> Handle<Image> {
+        let index = match piece_type {
+            PieceType::Pawn | PieceType::Empty => 0,
+            PieceType::Rook => 1,
+            PieceType::Knight => 2,
+            PieceType::Bishop => 3,
+            PieceType::Queen => 4,
+            PieceType::King => 5,
+        };
+        match piece_color {
+            PieceColor::White => self.white[index].clone(),
+            PieceColor::Black => self.black[index].clone(),
         }
+    }
+}
 
-        // Knights
-        {
-        let knight1 = commands.spawn((
-            Sprite {
-                image: black_knight.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(TILE_SIZE + tile_center, black_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Knight,
-                piece_color: PieceColor::Black,
-                position: (7, 2),
-                ..default()
 
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[7][1] = Some(knight1);
+pub fn create_pieces(
+    mut commands: Commands,
+    mut board_query: Query<(Entity, &mut ChessBoard), With<ChessBoard>>,
+    game_state: Res<GameState>,
+    asset_server: Res<AssetServer>
+){
+    let piece_sprites = PieceSprites {
+        white: [
+            asset_server.load("pieces/w_pawn.png"),
+            asset_server.load("pieces/w_rook.png"),
+            asset_server.load("pieces/w_knight.png"),
+            asset_server.load("pieces/w_bishop.png"),
+            asset_server.load("pieces/w_queen.png"),
+            asset_server.load("pieces/w_king.png"),
+        ],
+        black: [
+            asset_server.load("pieces/b_pawn.png"),
+            asset_server.load("pieces/b_rook.png"),
+            asset_server.load("pieces/b_knight.png"),
+            asset_server.load("pieces/b_bishop.png"),
+            asset_server.load("pieces/b_queen.png"),
+            asset_server.load("pieces/b_king.png"),
+        ],
+    };
 
-        let knight2 = commands.spawn((
-            Sprite {
-                image: black_knight.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(6.0 * TILE_SIZE + tile_center, black_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Knight,
-                piece_color: PieceColor::Black,
-                position: (7, 7),
-                ..default()
+    if let Ok((board_entity, mut chess_board)) = board_query.get_single_mut() {
+        spawn_pieces(&mut commands, board_entity, &mut chess_board, &game_state.position, &piece_sprites);
+    }
 
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[7][6] = Some(knight2);
-        }
+    commands.insert_resource(piece_sprites);
+}
 
-        // Bishops
-        {
-        let bishop1 = commands.spawn((
-            Sprite {
-                image: black_bishop.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(2.0 * TILE_SIZE + tile_center, black_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Bishop,
-                piece_color: PieceColor::Black,
-                position: (7, 3),
-                ..default()
+/// Spawns one `Piece` entity per occupied square of `position` and records it in `ChessBoard::pieces`.
+pub fn spawn_pieces(
+    commands: &mut Commands,
+    board_entity: Entity,
+    chess_board: &mut ChessBoard,
+    position: &Position,
+    piece_sprites: &PieceSprites,
+) {
+    let piece_scale = Vec3::new(PIECE_SCALE, PIECE_SCALE, 1.0);
 
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[7][2] = Some(bishop1);
+    for square in Square::all() {
+        let Some(board_piece) = position.piece_at(square) else { continue };
+        let (row, col) = square.into();
 
-        let bishop2 = commands.spawn((
+        let piece = commands.spawn((
             Sprite {
-                image: black_bishop.clone(),
+                image: piece_sprites.get(board_piece.piece_type, board_piece.color),
                 ..default()
             },
             Transform{
-                translation: Vec3::new(5.0 * TILE_SIZE + tile_center, black_pos_y, 1.0),
+                translation: tile_center(row, col).extend(1.0),
                 scale: piece_scale,
                 ..default()
             },
             Piece {
-                piece_type: PieceType::Bishop,
-                piece_color: PieceColor::Black,
-                position: (7, 6),
+                piece_type: board_piece.piece_type,
+                piece_color: board_piece.color,
+                position: square,
                 ..default()
-
-            } 
+            }
         )).set_parent(board_entity)
         .id();
-        chess_board.pieces[7][5] = Some(bishop2);
+        chess_board.set_piece(square, Some(piece));
+    }
+}
 
-        }
-        
-        // Queen
-        {
-        let queen = commands.spawn((
-            Sprite {
-                image: black_queen.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(3.0 * TILE_SIZE + tile_center, black_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::Queen,
-                piece_color: PieceColor::Black,
-                position: (7, 4),
-                ..default()
+pub fn update_piece_sprites(
+    mut pieces_query: Query<(&Piece, &mut Sprite), Changed<Piece>>,
+    piece_sprites: Option<Res<PieceSprites>>,
+) {
+    let Some(piece_sprites) = piece_sprites else { return };
 
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[7][3] = Some(queen);
+    for (piece, mut sprite) in pieces_query.iter_mut() {
+        let image = piece_sprites.get(piece.piece_type, piece.piece_color);
+        if sprite.image != image {
+            sprite.image = image;
         }
-        
-        // King
-        {
-        let king = commands.spawn((
-            Sprite {
-                image: black_king.clone(),
-                ..default()
-            },
-            Transform{
-                translation: Vec3::new(4.0 * TILE_SIZE + tile_center, black_pos_y, 1.0),
-                scale: piece_scale,
-                ..default()
-            },
-            Piece {
-                piece_type: PieceType::King,
-                piece_color: PieceColor::White,
-                position: (7, 5),
-                ..default()
+    }
+}
 
-            } 
-        )).set_parent(board_entity)
-        .id();
-        chess_board.pieces[7][4] = Some(king);
+pub fn animate_returning_pieces(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pieces_query: Query<(Entity, &mut Transform, &Piece, &mut ReturningPiece)>,
+) {
+    for (entity, mut transform, piece, mut returning) in pieces_query.iter_mut() {
+        returning.elapsed += time.delta_secs();
+        let progress = (returning.elapsed / PIECE_RETURN_TIME).min(1.0);
+        let eased = progress * progress * (3.0 - 2.0 * progress);
+
+        let (row, col) = piece.position.into();
+        let target = returning.start.lerp(tile_center(row, col), eased);
+        transform.translation = target.extend(transform.translation.z);
+        if progress >= 1.0 {
+            commands.entity(entity).remove::<ReturningPiece>();
         }
-            
-
-
-
     }
-
 }
@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+
+use crate::board::*;
+use crate::game::*;
+use crate::notation::*;
+use crate::pgn::*;
+use crate::pieces::*;
+use crate::resource_allocation::*;
+use crate::rules::Move;
+
+
+/// Games loaded from a PGN file for viewing. The board shows the current game after `cursor` moves.
+#[derive(Resource, Default)]
+pub struct Replay {
+    pub games: Vec<PgnGame>,
+    pub game_index: usize,
+    pub moves: Vec<Move>,
+    pub cursor: usize,
+}
+
+impl Replay {
+    pub fn is_active(&self) -> bool {
+        !self.games.is_empty()
+    }
+
+    pub fn current_game(&self) -> Option<&PgnGame> {
+        self.games.get(self.game_index)
+    }
+}
+
+
+/// Loads PGN files dropped onto the window and steps through them:
+/// Left/Right one move, Home/End to either end, PageUp/PageDown between games,
+/// Enter to leave the replay and play on from the shown position.
+#[allow(clippy::too_many_arguments)]
+pub fn replay_input(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut drop_events: EventReader<FileDragAndDrop>,
+    mut commands: Commands,
+    mut board_query: Query<(Entity, &mut ChessBoard), BoardFilter>,
+    mut pieces_query: PieceQuery,
+    piece_entities: Query<Entity, With<Piece>>,
+    mut game_state: ResMut<GameState>,
+    mut game_result: ResMut<GameResult>,
+    mut move_history: ResMut<MoveHistory>,
+    piece_sprites: Res<PieceSprites>,
+    mut replay: ResMut<Replay>,
+) {
+    let (board_entity, mut board_component) = board_query.single_mut();
+
+    let mut target = None;
+
+    for event in drop_events.read() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = event else { continue };
+
+        let games = match std::fs::read_to_string(path_buf).map_err(|error| error.to_string())
+            .and_then(|text| parse_pgn(&text).map_err(|error| error.to_string())) {
+            Ok(games) if !games.is_empty() => games,
+            Ok(_) => {
+                warn!("{} holds no games", path_buf.display());
+                continue;
+            }
+            Err(error) => {
+                warn!("Could not load {}: {error}", path_buf.display());
+                continue;
+            }
+        };
+
+        info!("Loaded {} game(s) from {}", games.len(), path_buf.display());
+        replay.games = games;
+        if open_game(&mut replay, 0) {
+            target = Some(0);
+        } else {
+            replay.games.clear();
+        }
+    }
+
+    if replay.is_active() {
+        let game_count = replay.games.len();
+        let game_index = replay.game_index;
+        let switched_game = (keyboard.just_pressed(KeyCode::PageDown) && open_game(&mut replay, (game_index + 1) % game_count))
+            || (keyboard.just_pressed(KeyCode::PageUp) && open_game(&mut replay, (game_index + game_count - 1) % game_count));
+        if switched_game {
+            target = Some(0);
+        } else if keyboard.just_pressed(KeyCode::ArrowLeft) && replay.cursor > 0 {
+            // Step back by undoing the last move, respawning the pieces only when it was not played here
+            if undo_move(&mut board_component, &mut pieces_query, &mut game_state, &mut move_history) {
+                move_history.redo.clear();
+                replay.cursor -= 1;
+            } else {
+                target = Some(replay.cursor - 1);
+            }
+        } else if keyboard.just_pressed(KeyCode::Home) {
+            target = Some(0);
+        } else if keyboard.just_pressed(KeyCode::End) {
+            target = Some(replay.moves.len());
+        } else if keyboard.just_pressed(KeyCode::ArrowRight) && target.is_none() && replay.cursor < replay.moves.len() {
+            // Step forward by moving the pieces that are already on the board
+            let mv = replay.moves[replay.cursor];
+            if let Some(entity) = board_component.piece_at(mv.from) {
+                let moved = process_piece_movement(
+                    &mut board_component,
+                    &mut pieces_query,
+                    &mut game_state.position,
+                    &mut move_history,
+                    (entity, mv.from),
+                    mv.to,
+                    mv.promotion,
+                );
+                if moved {
+                    replay.cursor += 1;
+                }
+            }
+        } else if keyboard.just_pressed(KeyCode::Enter) {
+            replay.games.clear();
+            replay.moves.clear();
+            replay.cursor = 0;
+            return;
+        }
+    }
+
+    let Some(target) = target else { return };
+    let Some(game) = replay.current_game() else { return };
+
+    // Rebuild the position `target` moves into the game and respawn the pieces for it
+    let mut position = game.start_position();
+    let start_fen = position.to_fen();
+    let mut sans = Vec::with_capacity(target);
+    for &mv in &replay.moves[..target] {
+        sans.push((mv, move_to_san(&position, mv)));
+        position.make_move(mv);
+    }
+
+    load_position(
+        &mut commands,
+        board_entity,
+        &mut board_component,
+        piece_entities.iter(),
+        &mut game_state,
+        &mut game_result,
+        &mut move_history,
+        &piece_sprites,
+        position,
+    );
+    move_history.start_fen = start_fen;
+    move_history.moves = sans;
+    replay.cursor = target;
+}
+
+/// Switches the replay to game `index`, returning false when its moves do not replay legally.
+fn open_game(replay: &mut Replay, index: usize) -> bool {
+    let Some(game) = replay.games.get(index) else { return false };
+
+    match game.replay() {
+        Ok(moves) => {
+            replay.game_index = index;
+            replay.moves = moves;
+            replay.cursor = 0;
+            true
+        }
+        Err(error) => {
+            warn!("Game {} cannot be replayed: {error}", index + 1);
+            false
+        }
+    }
+}
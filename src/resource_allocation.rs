@@ -1,6 +1,16 @@
 use bevy::{log::tracing_subscriber::field::debug, prelude::*};
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use crate::book::{BookError, BookSelection, OpeningBook};
 use crate::config::*;
+use crate::fen::STARTING_FEN;
+use crate::replay::Replay;
+use crate::rules::{GameOutcome, Move, PieceColor, Position, Square};
+use crate::search::{Difficulty, SearchLimits, SearchResult, Searcher};
+use crate::tablebase::Tablebases;
+use crate::uci::{UciEngine, UciError, UciInfo};
 
 #[derive(Resource)]
 pub struct BoardSettings{
@@ -27,15 +37,143 @@ pub struct PieceSettings{
     pub piece_scale: f32
 }
 
+#[derive(Resource)]
+pub struct GameSettings {
+    pub auto_queen: bool, // promote straight to a queen instead of asking
+    pub pgn_directory: std::path::PathBuf, // where exported games are written
+}
+
+/// Files read at startup, from config.rs unless given on the command line with
+/// `--engine <path>`, `--book <path>` or `--syzygy <dir>`. Parsed once, in `main`.
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct PathSettings {
+    pub engine: String, // empty for no external engine
+    pub book: String,
+    pub syzygy: String, // empty for no tablebases
+}
+
+impl PathSettings {
+    pub fn from_arguments(arguments: &[String]) -> Self {
+        let path = |flag: &str, configured: &str| {
+            arguments.iter().skip_while(|arg| *arg != flag).nth(1).cloned().unwrap_or_else(|| configured.to_string())
+        };
+        Self {
+            engine: path("--engine", UCI_ENGINE_PATH),
+            book: path("--book", OPENING_BOOK_PATH),
+            syzygy: path("--syzygy", SYZYGY_PATH),
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct GameState {
     pub selected_tile: Option<Entity>,
-    pub selected_piece: Option<(Entity, (usize, usize))>, // Entity, (row, col)>,
-    pub turn: bool, // true for white, false for black
+    pub selected_piece: Option<(Entity, Square)>, // Entity, square it stands on
+    pub position: Position, // rules-side mirror of the pieces on the board
+    pub pending_promotion: Option<((Entity, Square), Square)>, // pawn waiting for a promotion choice, destination
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EngineMode {
+    #[default]
+    Off,
+    Opponent, // the external engine plays the computer's moves
+    Analysis, // the external engine thinks about whatever position is shown
+}
+
+/// An external UCI engine, when one was configured and started.
+#[derive(Resource, Default)]
+pub struct ExternalEngine {
+    pub engine: Option<UciEngine>,
+    pub starting: Option<(String, Task<Result<UciEngine, UciError>>)>, // path, handshake still running
+    pub mode: EngineMode,
+    pub searching: Option<String>, // FEN of the position the engine is thinking about
+    pub analysis: Option<(String, UciInfo)>, // FEN, latest info the engine gave on it
+}
+
+/// Strength of the computer opponent. Starts from a preset, `limits` can be tuned from there.
+#[derive(Resource)]
+pub struct AiDifficulty {
+    pub level: Difficulty,
+    pub limits: SearchLimits,
+}
+
+impl AiDifficulty {
+    pub fn new(level: Difficulty) -> Self {
+        Self { level, limits: SearchLimits::preset(level) }
+    }
+}
+
+/// The opening book the computer plays from and the side panel lists, when one was found.
+#[derive(Resource)]
+pub struct Book {
+    pub book: Option<OpeningBook>,
+    pub selection: BookSelection,
+}
+
+/// Syzygy endgame tables, when a directory of them was configured.
+#[derive(Resource, Default)]
+pub struct Tablebase {
+    pub tablebases: Option<Tablebases>,
+}
+
+/// Every move played on the board since the current position was loaded.
+#[derive(Resource)]
+pub struct MoveHistory {
+    pub start_fen: String,
+    pub moves: Vec<(Move, String)>, // move, SAN
+    pub records: Vec<MoveRecord>, // one per move played here, for taking it back
+    pub redo: Vec<Move>, // moves taken back, most recent last; cleared by any new move
+}
+
+/// The entity side of a played move, for undoing it. Castling rights, en passant and
+/// the clocks are restored by `Position::unmake_move`.
+#[derive(Clone, Copy, Debug)]
+pub struct MoveRecord {
+    pub mv: Move,
+    pub piece: Entity,
+    pub had_moved: bool, // `Piece::has_moved` before the move
+    pub captured: Option<Entity>,
+    pub rook: Option<(Entity, bool)>, // castling rook and its `has_moved` before the move
+}
+
+#[derive(Resource, Default)]
+pub struct GameResult {
+    pub outcome: Option<GameOutcome>, // set once the game is over, input is frozen from then on
 }
 
+/// A piece picked up with the mouse, while the button is held.
+#[derive(Resource, Default)]
+pub struct PieceDrag {
+    pub piece: Option<(Entity, Square)>, // Entity, square it was picked up from
+    pub grab_point: Vec2, // cursor in board space when it was picked up
+    pub dragged: bool, // moved far enough to count as a drag; releasing then drops the piece
+}
 
-pub fn resource_allocation(mut commands: Commands){
+/// The computer opponent and the search it is running, if any.
+#[derive(Resource, Default)]
+pub struct AiPlayer {
+    pub color: Option<PieceColor>, // side the computer plays, None for two human players
+    pub task: Option<(String, Task<(Searcher, SearchResult)>)>, // FEN searched, search in progress
+    pub searcher: Option<Searcher>, // kept between moves for its transposition table, handed to the task while one runs
+    pub stop_flag: Option<Arc<AtomicBool>>, // the running search's flag, setting it ends that search early
+}
+
+impl AiPlayer {
+    /// Ends the running search, if there is one, and takes its searcher back so the
+    /// transposition table outlives the cancelled search.
+    pub fn cancel_search(&mut self) {
+        let Some((_, task)) = self.task.take() else { return };
+        if let Some(stop_flag) = self.stop_flag.take() {
+            stop_flag.store(true, Ordering::Relaxed);
+        }
+        let (searcher, _) = block_on(task);
+        self.searcher = Some(searcher);
+    }
+}
+
+
+pub fn resource_allocation(mut commands: Commands, paths: Res<PathSettings>){
 
     commands.insert_resource(WindowSettings{
         window_title: WINDOW_TITLE.to_string(),
@@ -62,7 +200,88 @@ pub fn resource_allocation(mut commands: Commands){
     commands.insert_resource(GameState{
         selected_tile: None,
         selected_piece: None,
-        turn: true
+        position: Position::starting(),
+        pending_promotion: None,
+    });
+
+    commands.insert_resource(AiDifficulty::new(AI_DIFFICULTY));
+
+    commands.insert_resource(GameSettings{
+        auto_queen: AUTO_QUEEN,
+        pgn_directory: PGN_EXPORT_DIR.into(),
     });
 
-}
\ No newline at end of file
+    commands.insert_resource(MoveHistory{
+        start_fen: STARTING_FEN.to_string(),
+        moves: Vec::new(),
+        records: Vec::new(),
+        redo: Vec::new(),
+    });
+
+    commands.insert_resource(GameResult::default());
+
+    commands.insert_resource(AiPlayer::default());
+
+    commands.insert_resource(PieceDrag::default());
+
+    // The engine's handshake can take seconds, it finishes in the background while the window opens
+    let starting = (!paths.engine.is_empty()).then(|| {
+        let engine_path = paths.engine.clone();
+        let task = AsyncComputeTaskPool::get().spawn(async move { UciEngine::start(std::path::Path::new(&engine_path)) });
+        (paths.engine.clone(), task)
+    });
+    commands.insert_resource(ExternalEngine { starting, ..default() });
+
+    commands.insert_resource(Replay::default());
+
+    let book_path = &paths.book;
+    // The book is optional: without one the computer searches from the first move
+    let book = match OpeningBook::load(std::path::Path::new(book_path)) {
+        Ok(book) => {
+            info!("Loaded opening book {book_path} ({} entries)", book.len());
+            Some(book)
+        }
+        Err(BookError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+            info!("No opening book at {book_path}, playing without one (put a Polyglot .bin there or pass --book <path>)");
+            None
+        }
+        Err(error) => {
+            warn!("Could not read opening book {book_path}, playing without one: {error}");
+            None
+        }
+    };
+    commands.insert_resource(Book { book, selection: BOOK_SELECTION });
+
+    let syzygy_path = &paths.syzygy;
+    let tablebases = if syzygy_path.is_empty() {
+        None
+    } else {
+        match Tablebases::open(std::path::Path::new(syzygy_path)) {
+            Ok(tablebases) => {
+                for path in tablebases.skipped() {
+                    warn!("Skipping {}: not a Syzygy table", path.display());
+                }
+                info!("Found {} Syzygy tables in {syzygy_path}, up to {} pieces", tablebases.len(), tablebases.max_pieces());
+                Some(tablebases)
+            }
+            Err(error) => {
+                warn!("Could not open Syzygy tables in {syzygy_path}: {error}");
+                None
+            }
+        }
+    };
+    commands.insert_resource(Tablebase { tablebases });
+
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_paths_override_the_configured_ones() {
+        let arguments: Vec<String> = ["--book", "openings.bin", "--syzygy", "/tables"].map(String::from).to_vec();
+        let paths = PathSettings::from_arguments(&arguments);
+        assert_eq!((paths.engine.as_str(), paths.book.as_str(), paths.syzygy.as_str()), (UCI_ENGINE_PATH, "openings.bin", "/tables"));
+        assert_eq!(PathSettings::from_arguments(&[]).book, OPENING_BOOK_PATH);
+    }
+}
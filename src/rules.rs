@@ -0,0 +1,922 @@
+//! Headless chess rules.
+//!
+//! Everything in here is plain Rust and knows nothing about Bevy, entities or
+//! sprites. The ECS side (`ChessBoard`, `Piece`) mirrors a [`Position`] and asks
+//! it which moves are possible, so the rules can be used and tested without an App.
+
+use crate::bitboard::*;
+use crate::zobrist;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PieceType {
+    Pawn,
+    Rook,
+    Knight,
+    Bishop,
+    Queen,
+    King,
+    Empty,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PieceColor {
+    White,
+    Black,
+}
+
+impl PieceColor {
+    pub fn opposite(self) -> Self {
+        match self {
+            PieceColor::White => PieceColor::Black,
+            PieceColor::Black => PieceColor::White,
+        }
+    }
+
+    /// Direction pawns of this colour move in, in rows.
+    pub fn forward(self) -> i32 {
+        match self {
+            PieceColor::White => 1,
+            PieceColor::Black => -1,
+        }
+    }
+}
+
+/// A square on the board. Rows and columns are 0-based and match
+/// `ChessBoard::pieces[row][col]`: row 0 is white's back rank, column 0 is the a-file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Square(u8);
+
+impl Square {
+    pub const fn new(row: usize, col: usize) -> Self {
+        Square((row * 8 + col) as u8)
+    }
+
+    pub const fn from_index(index: usize) -> Self {
+        Square(index as u8)
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub const fn row(self) -> usize {
+        self.0 as usize / 8
+    }
+
+    pub const fn col(self) -> usize {
+        self.0 as usize % 8
+    }
+
+    /// The square `rows` up and `cols` to the right, if it is still on the board.
+    pub fn offset(self, rows: i32, cols: i32) -> Option<Square> {
+        let row = self.row() as i32 + rows;
+        let col = self.col() as i32 + cols;
+        if (0..8).contains(&row) && (0..8).contains(&col) {
+            Some(Square::new(row as usize, col as usize))
+        } else {
+            None
+        }
+    }
+
+    pub fn all() -> impl Iterator<Item = Square> {
+        (0..64).map(Square::from_index)
+    }
+}
+
+impl From<(usize, usize)> for Square {
+    fn from((row, col): (usize, usize)) -> Self {
+        Square::new(row, col)
+    }
+}
+
+impl From<Square> for (usize, usize) {
+    fn from(square: Square) -> Self {
+        (square.row(), square.col())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BoardPiece {
+    pub piece_type: PieceType,
+    pub color: PieceColor,
+}
+
+impl BoardPiece {
+    pub const fn new(piece_type: PieceType, color: PieceColor) -> Self {
+        Self { piece_type, color }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CastlingRights {
+    pub white_king_side: bool,
+    pub white_queen_side: bool,
+    pub black_king_side: bool,
+    pub black_queen_side: bool,
+}
+
+impl CastlingRights {
+    pub const ALL: Self = Self {
+        white_king_side: true,
+        white_queen_side: true,
+        black_king_side: true,
+        black_queen_side: true,
+    };
+
+    pub const NONE: Self = Self {
+        white_king_side: false,
+        white_queen_side: false,
+        black_king_side: false,
+        black_queen_side: false,
+    };
+
+    /// Drops whichever right is tied to a rook standing on `square`.
+    fn clear_for_rook_square(&mut self, square: Square) {
+        match (square.row(), square.col()) {
+            (0, 0) => self.white_queen_side = false,
+            (0, 7) => self.white_king_side = false,
+            (7, 0) => self.black_queen_side = false,
+            (7, 7) => self.black_king_side = false,
+            _ => {}
+        }
+    }
+
+    fn clear_for_color(&mut self, color: PieceColor) {
+        match color {
+            PieceColor::White => {
+                self.white_king_side = false;
+                self.white_queen_side = false;
+            }
+            PieceColor::Black => {
+                self.black_king_side = false;
+                self.black_queen_side = false;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MoveKind {
+    Normal,
+    DoublePush,
+    Castle,
+    EnPassant,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    pub kind: MoveKind,
+    pub promotion: Option<PieceType>,
+}
+
+impl Move {
+    pub const fn new(from: Square, to: Square) -> Self {
+        Self { from, to, kind: MoveKind::Normal, promotion: None }
+    }
+
+    pub const fn with_kind(from: Square, to: Square, kind: MoveKind) -> Self {
+        Self { from, to, kind, promotion: None }
+    }
+
+    /// The square of the piece this move would capture. Only differs from `to` for en passant,
+    /// where the passed pawn sits beside the moving pawn rather than on its destination.
+    pub fn capture_square(&self) -> Square {
+        match self.kind {
+            MoveKind::EnPassant => Square::new(self.from.row(), self.to.col()),
+            _ => self.to,
+        }
+    }
+
+    /// For a castling move, where the rook comes from and where it lands.
+    pub fn castling_rook(&self) -> Option<(Square, Square)> {
+        if self.kind != MoveKind::Castle {
+            return None;
+        }
+
+        let row = self.from.row();
+        if self.to.col() > self.from.col() {
+            Some((Square::new(row, 7), Square::new(row, 5)))
+        } else {
+            Some((Square::new(row, 0), Square::new(row, 3)))
+        }
+    }
+}
+
+/// How a finished game ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GameOutcome {
+    Checkmate { winner: PieceColor },
+    Stalemate,
+    Draw(DrawReason),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DrawReason {
+    InsufficientMaterial,
+    /// 75 moves by each side without a capture or pawn move, applied automatically.
+    SeventyFiveMoveRule,
+    /// Same position for the fifth time, applied automatically.
+    FivefoldRepetition,
+    /// 50 moves by each side without a capture or pawn move, on claim.
+    FiftyMoveRule,
+    /// Same position for the third time, on claim.
+    ThreefoldRepetition,
+}
+
+/// Everything `make_move` overwrites, so `unmake_move` can put it back.
+#[derive(Clone, Copy, Debug)]
+struct Undo {
+    mv: Move,
+    captured: Option<BoardPiece>,
+    castling: CastlingRights,
+    en_passant: Option<Square>,
+    halfmove_clock: u32,
+    zobrist: u64,
+    hash: u64, // position before the move, for repetition detection
+}
+
+/// Pieces a pawn may promote to, strongest first.
+pub const PROMOTION_PIECES: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+const BACK_RANK: [PieceType; 8] = [
+    PieceType::Rook,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Queen,
+    PieceType::King,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Rook,
+];
+
+#[derive(Clone, Debug)]
+pub struct Position {
+    board: [Option<BoardPiece>; 64],
+    pieces: [Bitboard; 6], // squares of each piece type, both colours, indexed by `type_index`
+    colors: [Bitboard; 2], // white, black
+    side_to_move: PieceColor,
+    castling: CastlingRights,
+    en_passant: Option<Square>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    history: Vec<Undo>,
+    zobrist: u64, // pieces, castling and side to move; `hash` adds en passant
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::starting()
+    }
+}
+
+impl Position {
+    /// A board with no pieces on it and white to move.
+    pub fn empty() -> Self {
+        Self {
+            board: [None; 64],
+            pieces: [Bitboard::EMPTY; 6],
+            colors: [Bitboard::EMPTY; 2],
+            side_to_move: PieceColor::White,
+            castling: CastlingRights::NONE,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            history: Vec::new(),
+            zobrist: zobrist::white_to_move_key(),
+        }
+    }
+
+    /// The standard starting position.
+    pub fn starting() -> Self {
+        let mut position = Self::empty();
+        for (col, piece_type) in BACK_RANK.iter().enumerate() {
+            position.set_piece(Square::new(0, col), Some(BoardPiece::new(*piece_type, PieceColor::White)));
+            position.set_piece(Square::new(1, col), Some(BoardPiece::new(PieceType::Pawn, PieceColor::White)));
+            position.set_piece(Square::new(6, col), Some(BoardPiece::new(PieceType::Pawn, PieceColor::Black)));
+            position.set_piece(Square::new(7, col), Some(BoardPiece::new(*piece_type, PieceColor::Black)));
+        }
+        position.set_castling_rights(CastlingRights::ALL);
+        position
+    }
+
+    pub fn piece_at(&self, square: Square) -> Option<BoardPiece> {
+        self.board[square.index()]
+    }
+
+    pub fn set_piece(&mut self, square: Square, piece: Option<BoardPiece>) {
+        let bit = Bitboard::from_square(square);
+        if let Some(old) = self.board[square.index()] {
+            self.zobrist ^= zobrist::piece_key(old, square);
+            self.colors[color_index(old.color)] &= !bit;
+            if let Some(index) = type_index(old.piece_type) {
+                self.pieces[index] &= !bit;
+            }
+        }
+        if let Some(new) = piece {
+            self.zobrist ^= zobrist::piece_key(new, square);
+            self.colors[color_index(new.color)] |= bit;
+            if let Some(index) = type_index(new.piece_type) {
+                self.pieces[index] |= bit;
+            }
+        }
+        self.board[square.index()] = piece;
+    }
+
+    /// Squares holding a `piece_type` of `color`.
+    pub fn pieces(&self, piece_type: PieceType, color: PieceColor) -> Bitboard {
+        match type_index(piece_type) {
+            Some(index) => self.pieces[index] & self.colors[color_index(color)],
+            None => Bitboard::EMPTY,
+        }
+    }
+
+    /// Squares holding any piece of `color`.
+    pub fn pieces_of(&self, color: PieceColor) -> Bitboard {
+        self.colors[color_index(color)]
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub fn side_to_move(&self) -> PieceColor {
+        self.side_to_move
+    }
+
+    pub fn set_side_to_move(&mut self, color: PieceColor) {
+        if color != self.side_to_move {
+            self.zobrist ^= zobrist::white_to_move_key();
+        }
+        self.side_to_move = color;
+    }
+
+    pub fn castling_rights(&self) -> CastlingRights {
+        self.castling
+    }
+
+    pub fn set_castling_rights(&mut self, castling: CastlingRights) {
+        self.zobrist ^= zobrist::castling_key(self.castling) ^ zobrist::castling_key(castling);
+        self.castling = castling;
+    }
+
+    pub fn en_passant(&self) -> Option<Square> {
+        self.en_passant
+    }
+
+    pub fn set_en_passant(&mut self, square: Option<Square>) {
+        self.en_passant = square;
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    pub fn set_clocks(&mut self, halfmove_clock: u32, fullmove_number: u32) {
+        self.halfmove_clock = halfmove_clock;
+        self.fullmove_number = fullmove_number;
+    }
+
+    /// Moves made on this position that can still be taken back, oldest first.
+    pub fn played_moves(&self) -> impl Iterator<Item = Move> + '_ {
+        self.history.iter().map(|undo| undo.mv)
+    }
+
+    /// All moves for the side to move that do not leave its own king in check.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(64);
+        for square in self.pieces_of(self.side_to_move).squares() {
+            self.generate_piece_moves(square, &mut moves);
+        }
+        moves.retain(|mv| self.is_legal(*mv));
+        moves
+    }
+
+    /// Legal moves of whatever piece stands on `from`, regardless of whose turn it is.
+    pub fn moves_from(&self, from: Square) -> Vec<Move> {
+        let mut moves = Vec::new();
+        self.generate_piece_moves(from, &mut moves);
+        moves.retain(|mv| self.is_legal(*mv));
+        moves
+    }
+
+    pub fn king_square(&self, color: PieceColor) -> Option<Square> {
+        self.pieces(PieceType::King, color).first()
+    }
+
+    /// Whether any piece of colour `by` attacks `square`.
+    pub fn is_square_attacked(&self, square: Square, by: PieceColor) -> bool {
+        !self.attackers(square, by, self.occupied()).is_empty()
+    }
+
+    /// Pieces of colour `by` attacking `square` when the squares in `occupied` are filled.
+    fn attackers(&self, square: Square, by: PieceColor, occupied: Bitboard) -> Bitboard {
+        let queens = self.pieces(PieceType::Queen, by);
+        // A pawn of `by` attacks `square` from wherever a pawn of the other colour on `square` would attack
+        (pawn_attacks(by.opposite(), square) & self.pieces(PieceType::Pawn, by))
+            | (knight_attacks(square) & self.pieces(PieceType::Knight, by))
+            | (king_attacks(square) & self.pieces(PieceType::King, by))
+            | (bishop_attacks(square, occupied) & (self.pieces(PieceType::Bishop, by) | queens))
+            | (rook_attacks(square, occupied) & (self.pieces(PieceType::Rook, by) | queens))
+    }
+
+    /// Whether the side to move is in check.
+    pub fn is_in_check(&self) -> bool {
+        self.king_square(self.side_to_move)
+            .is_some_and(|king| self.is_square_attacked(king, self.side_to_move.opposite()))
+    }
+
+    /// How the game has ended for the side to move, `None` while it goes on.
+    /// Only draws that apply automatically are reported, see `claimable_draw` for the rest.
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        if self.legal_moves().is_empty() {
+            return if self.is_in_check() {
+                Some(GameOutcome::Checkmate { winner: self.side_to_move.opposite() })
+            } else {
+                Some(GameOutcome::Stalemate)
+            };
+        }
+
+        if self.has_insufficient_material() {
+            Some(GameOutcome::Draw(DrawReason::InsufficientMaterial))
+        } else if self.halfmove_clock >= 150 {
+            Some(GameOutcome::Draw(DrawReason::SeventyFiveMoveRule))
+        } else if self.repetition_count() >= 5 {
+            Some(GameOutcome::Draw(DrawReason::FivefoldRepetition))
+        } else {
+            None
+        }
+    }
+
+    /// A draw the side to move could claim right now, if any.
+    pub fn claimable_draw(&self) -> Option<DrawReason> {
+        if self.halfmove_clock >= 100 {
+            Some(DrawReason::FiftyMoveRule)
+        } else if self.repetition_count() >= 3 {
+            Some(DrawReason::ThreefoldRepetition)
+        } else {
+            None
+        }
+    }
+
+    /// How many times the current position has occurred, counting this one.
+    /// Only positions since the last capture or pawn move can match.
+    pub fn repetition_count(&self) -> usize {
+        let hash = self.hash();
+        let reversible = self.history.iter().rev().take(self.halfmove_clock as usize);
+        1 + reversible.filter(|undo| undo.hash == hash).count()
+    }
+
+    /// Neither side can possibly mate: bare kings, a single minor piece,
+    /// or only bishops that all stand on squares of the same colour.
+    pub fn has_insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_square_colors = [false; 2];
+
+        for square in Square::all() {
+            let Some(piece) = self.piece_at(square) else { continue };
+            match piece.piece_type {
+                PieceType::King | PieceType::Empty => {}
+                PieceType::Knight => knights += 1,
+                PieceType::Bishop => bishop_square_colors[(square.row() + square.col()) % 2] = true,
+                PieceType::Pawn | PieceType::Rook | PieceType::Queen => return false,
+            }
+        }
+
+        let bishop_colors = bishop_square_colors.iter().filter(|&&present| present).count();
+        match knights {
+            0 => bishop_colors <= 1,
+            1 => bishop_colors == 0,
+            _ => false,
+        }
+    }
+
+    /// Zobrist key of the position, kept up to date move by move. It is the key
+    /// Polyglot books use, so it serves repetition checks, the search's table and book lookups.
+    pub fn hash(&self) -> u64 {
+        match self.en_passant {
+            Some(square) if self.pawn_can_reach_en_passant(square) => self.zobrist ^ zobrist::en_passant_key(square.col()),
+            _ => self.zobrist,
+        }
+    }
+
+    /// Whether a pawn of the side to move stands next to the pawn that just double-pushed.
+    /// Polyglot only hashes the en passant square then, whether or not the capture is legal.
+    pub fn pawn_can_reach_en_passant(&self, square: Square) -> bool {
+        let pawn = Some(BoardPiece::new(PieceType::Pawn, self.side_to_move));
+        [-1, 1].iter().any(|&side| {
+            square.offset(-self.side_to_move.forward(), side)
+                .is_some_and(|from| self.piece_at(from) == pawn)
+        })
+    }
+
+    /// Works out the occupancy after `mv` and checks the mover's king is not attacked in it.
+    fn is_legal(&self, mv: Move) -> bool {
+        let Some(piece) = self.piece_at(mv.from) else { return false };
+
+        let captured = Bitboard::from_square(mv.capture_square());
+        let mut occupied = ((self.occupied() ^ Bitboard::from_square(mv.from)) & !captured) | Bitboard::from_square(mv.to);
+        if let Some((rook_from, rook_to)) = mv.castling_rook() {
+            occupied = (occupied ^ Bitboard::from_square(rook_from)) | Bitboard::from_square(rook_to);
+        }
+
+        let king = if piece.piece_type == PieceType::King { Some(mv.to) } else { self.king_square(piece.color) };
+        match king {
+            Some(king) => {
+                let enemies = self.pieces_of(piece.color.opposite()) & !captured;
+                (self.attackers(king, piece.color.opposite(), occupied) & enemies).is_empty()
+            }
+            None => true,
+        }
+    }
+
+    /// Looks up the move of the piece on `from` that lands on `to`, if there is one and it
+    /// belongs to the side to move. Pawn moves onto the last rank only match when
+    /// `promotion` names the new piece.
+    pub fn find_move(&self, from: Square, to: Square, promotion: Option<PieceType>) -> Option<Move> {
+        self.turn_moves_from(from).into_iter().find(|mv| mv.to == to && mv.promotion == promotion)
+    }
+
+    /// Whether moving the piece on `from` to `to` is a legal pawn promotion for the side to move.
+    pub fn is_promotion(&self, from: Square, to: Square) -> bool {
+        self.turn_moves_from(from).iter().any(|mv| mv.to == to && mv.promotion.is_some())
+    }
+
+    /// `moves_from`, but empty unless the piece on `from` is the side to move's.
+    fn turn_moves_from(&self, from: Square) -> Vec<Move> {
+        match self.piece_at(from) {
+            Some(piece) if piece.color == self.side_to_move => self.moves_from(from),
+            _ => Vec::new(),
+        }
+    }
+
+    fn generate_piece_moves(&self, from: Square, moves: &mut Vec<Move>) {
+        let Some(piece) = self.piece_at(from) else { return };
+
+        let occupied = self.occupied();
+        let targets = match piece.piece_type {
+            PieceType::Pawn => {
+                self.generate_pawn_moves(from, piece.color, moves);
+                return;
+            }
+            PieceType::Knight => knight_attacks(from),
+            PieceType::King => {
+                self.generate_castling_moves(from, piece.color, moves);
+                king_attacks(from)
+            }
+            PieceType::Rook => rook_attacks(from, occupied),
+            PieceType::Bishop => bishop_attacks(from, occupied),
+            PieceType::Queen => queen_attacks(from, occupied),
+            PieceType::Empty => return,
+        };
+        let targets = targets & !self.pieces_of(piece.color);
+        moves.extend(targets.squares().map(|to| Move::new(from, to)));
+    }
+
+    fn generate_pawn_moves(&self, from: Square, color: PieceColor, moves: &mut Vec<Move>) {
+        let forward = color.forward();
+        let start_row = match color {
+            PieceColor::White => 1,
+            PieceColor::Black => 6,
+        };
+
+        if let Some(one_step) = from.offset(forward, 0)
+            && self.piece_at(one_step).is_none()
+        {
+            push_pawn_move(Move::new(from, one_step), moves);
+
+            if from.row() == start_row
+                && let Some(two_step) = from.offset(2 * forward, 0)
+                && self.piece_at(two_step).is_none()
+            {
+                moves.push(Move::with_kind(from, two_step, MoveKind::DoublePush));
+            }
+        }
+
+        let attacks = pawn_attacks(color, from);
+        for target in (attacks & self.pieces_of(color.opposite())).squares() {
+            push_pawn_move(Move::new(from, target), moves);
+        }
+        if let Some(target) = self.en_passant
+            && attacks.contains(target)
+            && color == self.side_to_move
+        {
+            moves.push(Move::with_kind(from, target, MoveKind::EnPassant));
+        }
+    }
+
+    fn generate_castling_moves(&self, from: Square, color: PieceColor, moves: &mut Vec<Move>) {
+        let (row, king_side, queen_side) = match color {
+            PieceColor::White => (0, self.castling.white_king_side, self.castling.white_queen_side),
+            PieceColor::Black => (7, self.castling.black_king_side, self.castling.black_queen_side),
+        };
+        if from != Square::new(row, 4) || !(king_side || queen_side) {
+            return;
+        }
+
+        let enemy = color.opposite();
+        if self.is_square_attacked(from, enemy) {
+            return;
+        }
+
+        let empty = |cols: &[usize]| cols.iter().all(|&col| self.piece_at(Square::new(row, col)).is_none());
+        let safe = |cols: &[usize]| cols.iter().all(|&col| !self.is_square_attacked(Square::new(row, col), enemy));
+        let rook_home = |col: usize| self.piece_at(Square::new(row, col)) == Some(BoardPiece::new(PieceType::Rook, color));
+
+        // The king may not pass through check; landing in check is caught by the legality filter
+        if king_side && rook_home(7) && empty(&[5, 6]) && safe(&[5]) {
+            moves.push(Move::with_kind(from, Square::new(row, 6), MoveKind::Castle));
+        }
+        if queen_side && rook_home(0) && empty(&[1, 2, 3]) && safe(&[3]) {
+            moves.push(Move::with_kind(from, Square::new(row, 2), MoveKind::Castle));
+        }
+    }
+
+    /// Plays `mv` on the board. The move is expected to come from `legal_moves`
+    /// or `moves_from`; nothing is re-validated here.
+    pub fn make_move(&mut self, mv: Move) {
+        let hash = self.hash();
+        let zobrist = self.zobrist;
+        let piece = self.piece_at(mv.from).expect("make_move: no piece on the from square");
+        let captured = self.piece_at(mv.capture_square());
+        self.set_piece(mv.from, None);
+        self.set_piece(mv.capture_square(), None);
+        self.set_piece(mv.to, match mv.promotion {
+            Some(promotion) => Some(BoardPiece::new(promotion, piece.color)),
+            None => Some(piece),
+        });
+
+        self.history.push(Undo {
+            mv,
+            captured,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            zobrist,
+            hash,
+        });
+
+        if piece.piece_type == PieceType::Pawn || captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+
+        if let Some((rook_from, rook_to)) = mv.castling_rook() {
+            let rook = self.piece_at(rook_from);
+            self.set_piece(rook_from, None);
+            self.set_piece(rook_to, rook);
+        }
+
+        self.en_passant = match mv.kind {
+            MoveKind::DoublePush => mv.from.offset(piece.color.forward(), 0),
+            _ => None,
+        };
+
+        let mut castling = self.castling;
+        if piece.piece_type == PieceType::King {
+            castling.clear_for_color(piece.color);
+        }
+        castling.clear_for_rook_square(mv.from);
+        castling.clear_for_rook_square(mv.to);
+        self.set_castling_rights(castling);
+
+        if piece.color == PieceColor::Black {
+            self.fullmove_number += 1;
+        }
+        self.set_side_to_move(self.side_to_move.opposite());
+    }
+
+    /// Takes back the last move played with `make_move` and returns it.
+    pub fn unmake_move(&mut self) -> Option<Move> {
+        let undo = self.history.pop()?;
+        let mv = undo.mv;
+
+        let piece = self.piece_at(mv.to);
+        self.set_piece(mv.to, None);
+        self.set_piece(mv.from, match mv.promotion {
+            Some(_) => piece.map(|promoted| BoardPiece::new(PieceType::Pawn, promoted.color)),
+            None => piece,
+        });
+        self.set_piece(mv.capture_square(), undo.captured);
+
+        if let Some((rook_from, rook_to)) = mv.castling_rook() {
+            let rook = self.piece_at(rook_to);
+            self.set_piece(rook_to, None);
+            self.set_piece(rook_from, rook);
+        }
+
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.zobrist = undo.zobrist;
+        self.side_to_move = self.side_to_move.opposite();
+        if self.side_to_move == PieceColor::Black {
+            self.fullmove_number -= 1;
+        }
+
+        Some(mv)
+    }
+}
+
+/// Pushes a pawn move, expanded into one move per promotion piece when it reaches the last rank.
+fn push_pawn_move(mv: Move, moves: &mut Vec<Move>) {
+    if mv.to.row() == 0 || mv.to.row() == 7 {
+        moves.extend(PROMOTION_PIECES.iter().map(|&promotion| Move { promotion: Some(promotion), ..mv }));
+    } else {
+        moves.push(mv);
+    }
+}
+
+fn type_index(piece_type: PieceType) -> Option<usize> {
+    match piece_type {
+        PieceType::Pawn => Some(0),
+        PieceType::Knight => Some(1),
+        PieceType::Bishop => Some(2),
+        PieceType::Rook => Some(3),
+        PieceType::Queen => Some(4),
+        PieceType::King => Some(5),
+        PieceType::Empty => None,
+    }
+}
+
+fn color_index(color: PieceColor) -> usize {
+    match color {
+        PieceColor::White => 0,
+        PieceColor::Black => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(name: &str) -> Square {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn unmake_restores_every_move() {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ];
+        for fen in fens {
+            let mut position = Position::from_fen(fen).unwrap();
+            let hash = position.hash();
+            for mv in position.legal_moves() {
+                position.make_move(mv);
+                for reply in position.legal_moves() {
+                    position.make_move(reply);
+                    assert_eq!(position.unmake_move(), Some(reply));
+                }
+                assert_eq!(position.unmake_move(), Some(mv));
+                assert_eq!((position.to_fen().as_str(), position.hash()), (fen, hash), "{mv:?}");
+            }
+        }
+        assert_eq!(Position::starting().unmake_move(), None);
+    }
+
+    #[test]
+    fn find_move_only_answers_for_the_side_to_move() {
+        let mut position = Position::starting();
+        assert!(position.find_move(square("e7"), square("e5"), None).is_none());
+        assert!(position.find_move(square("e2"), square("e4"), None).is_some());
+
+        position.make_move(position.find_move(square("e2"), square("e4"), None).unwrap());
+        assert!(position.find_move(square("d2"), square("d4"), None).is_none());
+        assert!(position.find_move(square("e7"), square("e5"), None).is_some());
+
+        let position = Position::from_fen("4k3/P7/8/8/8/8/7p/4K3 b - - 0 1").unwrap();
+        assert!(!position.is_promotion(square("a7"), square("a8")));
+        assert!(position.find_move(square("a7"), square("a8"), Some(PieceType::Queen)).is_none());
+        assert!(position.is_promotion(square("h2"), square("h1")));
+    }
+
+    #[test]
+    fn find_move_rejects_illegal_moves() {
+        // The e4 knight is pinned to its king, the d1 square is covered by the h5 queen
+        let position = Position::from_fen("4r2k/8/8/7q/4N3/8/8/4K3 w - - 0 1").unwrap();
+        assert!(position.find_move(square("e4"), square("f6"), None).is_none());
+        assert!(position.find_move(square("e1"), square("d1"), None).is_none());
+        assert!(position.find_move(square("e1"), square("e3"), None).is_none());
+        assert!(position.find_move(square("e1"), square("f2"), None).is_some());
+        assert!(position.find_move(square("a1"), square("a2"), None).is_none());
+
+        let position = Position::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert!(position.find_move(square("a7"), square("a8"), None).is_none());
+        assert!(position.find_move(square("a7"), square("a8"), Some(PieceType::Knight)).is_some());
+        assert!(position.find_move(square("a7"), square("a8"), Some(PieceType::King)).is_none());
+    }
+
+    #[test]
+    fn recognises_mates_on_the_board() {
+        let back_rank = Position::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 1 1").unwrap();
+        let smothered = Position::from_fen("6rk/5Npp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        for position in [back_rank, smothered] {
+            assert!(position.is_in_check());
+            assert_eq!(position.outcome(), Some(GameOutcome::Checkmate { winner: PieceColor::White }));
+        }
+
+        // One move earlier the back rank can still be covered
+        let before = Position::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        assert!(!before.is_in_check());
+        assert_eq!(before.outcome(), None);
+    }
+
+    #[test]
+    fn a_king_with_no_moves_out_of_check_is_stalemated() {
+        let position = Position::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(!position.is_in_check());
+        assert_eq!(position.outcome(), Some(GameOutcome::Stalemate));
+    }
+
+    #[test]
+    fn a_piece_moving_out_of_the_way_can_give_check() {
+        let mut position = Position::from_fen("4k3/8/8/8/4N3/8/8/K3R3 w - - 0 1").unwrap();
+        position.make_move(position.find_move(square("e4"), square("c5"), None).unwrap());
+        assert!(position.is_in_check());
+        assert!(position.legal_moves().iter().all(|mv| mv.from == square("e8") && mv.to.col() != 4));
+        assert_eq!(position.outcome(), None);
+    }
+
+    fn play(position: &mut Position, moves: &str) {
+        for uci in moves.split_whitespace() {
+            position.make_move(crate::notation::parse_uci(position, uci).unwrap());
+        }
+    }
+
+    #[test]
+    fn knows_when_neither_side_can_mate() {
+        let cases = [
+            ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),  // bishops on dark squares
+            ("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1", false), // one dark, one light
+            ("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", false),
+        ];
+        for (fen, insufficient) in cases {
+            let position = Position::from_fen(fen).unwrap();
+            assert_eq!(position.has_insufficient_material(), insufficient, "{fen}");
+            assert_eq!(position.outcome() == Some(GameOutcome::Draw(DrawReason::InsufficientMaterial)), insufficient, "{fen}");
+        }
+    }
+
+    #[test]
+    fn repetitions_can_be_claimed_at_three_and_end_the_game_at_five() {
+        let mut position = Position::starting();
+        play(&mut position, "g1f3 g8f6 f3g1 f6g8");
+        assert_eq!((position.repetition_count(), position.claimable_draw()), (2, None));
+
+        play(&mut position, "g1f3 g8f6 f3g1 f6g8");
+        assert_eq!(position.repetition_count(), 3);
+        assert_eq!(position.claimable_draw(), Some(DrawReason::ThreefoldRepetition));
+        assert_eq!(position.outcome(), None);
+
+        play(&mut position, "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8");
+        assert_eq!(position.repetition_count(), 5);
+        assert_eq!(position.outcome(), Some(GameOutcome::Draw(DrawReason::FivefoldRepetition)));
+    }
+
+    #[test]
+    fn fifty_moves_can_be_claimed_and_seventy_five_end_the_game() {
+        let clock = |halfmoves: u32| Position::from_fen(&format!("4k3/8/8/8/8/8/8/R3K3 w - - {halfmoves} 90")).unwrap();
+        assert_eq!(clock(99).claimable_draw(), None);
+        assert_eq!(clock(100).claimable_draw(), Some(DrawReason::FiftyMoveRule));
+        assert_eq!(clock(100).outcome(), None);
+        assert_eq!(clock(149).outcome(), None);
+        assert_eq!(clock(150).outcome(), Some(GameOutcome::Draw(DrawReason::SeventyFiveMoveRule)));
+
+        let mut position = clock(99);
+        play(&mut position, "a1a2");
+        assert_eq!(position.claimable_draw(), Some(DrawReason::FiftyMoveRule));
+    }
+
+    #[test]
+    fn losing_castling_or_en_passant_rights_makes_a_new_position() {
+        // The rooks go back where they were, but neither side can castle queenside any more
+        let mut position = Position::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        play(&mut position, "a1b1 a8b8 b1a1 b8a8");
+        assert_eq!(position.repetition_count(), 1);
+        play(&mut position, "a1b1 a8b8 b1a1 b8a8");
+        assert_eq!(position.repetition_count(), 2);
+
+        // Only straight after e2e4 could the d4 pawn take en passant
+        let mut position = Position::from_fen("4k3/8/8/8/3p4/8/4P3/4K3 w - - 0 1").unwrap();
+        play(&mut position, "e2e4 e8d8 e1d1 d8e8 d1e1");
+        assert_eq!(position.repetition_count(), 1);
+        play(&mut position, "e8d8 e1d1 d8e8 d1e1");
+        assert_eq!(position.repetition_count(), 2);
+    }
+}
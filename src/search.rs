@@ -0,0 +1,566 @@
+//! Computer player: iterative deepening alpha-beta over [`Position`], with
+//! quiescence search, a transposition table and simple move ordering.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::rules::*;
+
+/// Score of being mated right now; mates further away score closer to zero.
+pub const MATE_SCORE: i32 = 100_000;
+const INFINITY: i32 = MATE_SCORE + 1;
+/// Scores beyond this are mates, which the transposition table stores relative to the node.
+pub const MATE_THRESHOLD: i32 = MATE_SCORE - 1_000;
+
+const TABLE_SIZE: usize = 1 << 18;
+/// How often, in nodes, the clock is looked at.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+/// Deepest iteration tried when only the clock limits the search.
+pub const MAX_DEPTH: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    Beginner,
+    Easy,
+    Medium,
+    Hard,
+    Master,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 5] = [
+        Difficulty::Beginner,
+        Difficulty::Easy,
+        Difficulty::Medium,
+        Difficulty::Hard,
+        Difficulty::Master,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "Beginner",
+            Difficulty::Easy => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard => "Hard",
+            Difficulty::Master => "Master",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchLimits {
+    pub max_depth: u32,
+    pub time_limit: Duration,
+    pub eval_noise: i32, // up to this many centipawns are added to or taken from every evaluation
+    pub blunder_chance: f64, // probability of playing a random legal move instead of the best one
+}
+
+impl SearchLimits {
+    /// Full strength within `time_limit`.
+    pub fn timed(time_limit: Duration) -> Self {
+        Self { max_depth: MAX_DEPTH, time_limit, eval_noise: 0, blunder_chance: 0.0 }
+    }
+
+    pub fn preset(difficulty: Difficulty) -> Self {
+        let (max_depth, time_limit_ms, eval_noise, blunder_chance) = match difficulty {
+            Difficulty::Beginner => (1, 200, 150, 0.25),
+            Difficulty::Easy => (2, 400, 80, 0.10),
+            Difficulty::Medium => (4, 1000, 30, 0.03),
+            Difficulty::Hard => (MAX_DEPTH, 1500, 0, 0.0),
+            Difficulty::Master => (MAX_DEPTH, 5000, 0, 0.0),
+        };
+        Self { max_depth, time_limit: Duration::from_millis(time_limit_ms), eval_noise, blunder_chance }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32, // centipawns from the side to move's point of view
+    pub depth: u32, // deepest iteration that finished
+    pub nodes: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TableEntry {
+    hash: u64,
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best_move: Option<Move>,
+}
+
+pub struct Searcher {
+    table: Vec<Option<TableEntry>>,
+    nodes: u64,
+    deadline: Instant,
+    stopped: bool,
+    root_best: Option<(Move, i32)>,
+    eval_noise: i32,
+    noise_seed: u64,
+    stop_flag: Arc<AtomicBool>,
+}
+
+impl Default for Searcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Self {
+        Self {
+            table: vec![None; TABLE_SIZE],
+            nodes: 0,
+            deadline: Instant::now(),
+            stopped: false,
+            root_best: None,
+            eval_noise: 0,
+            noise_seed: 0,
+            stop_flag: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Setting the returned flag ends a running search early. It stays set, so clear it
+    /// again before the searcher is used for the next search.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop_flag.clone()
+    }
+
+    /// Searches one ply deeper at a time until `limits` run out and returns the
+    /// best move of the last iteration that finished. The position is left as it was.
+    pub fn search(&mut self, position: &mut Position, limits: SearchLimits) -> SearchResult {
+        self.search_with_report(position, limits, |_, _| {})
+    }
+
+    /// `search`, calling `report` with the result and principal variation of every iteration that finishes.
+    pub fn search_with_report(
+        &mut self,
+        position: &mut Position,
+        limits: SearchLimits,
+        mut report: impl FnMut(&SearchResult, &[Move]),
+    ) -> SearchResult {
+        self.nodes = 0;
+        self.deadline = Instant::now() + limits.time_limit;
+        self.stopped = false;
+        self.eval_noise = limits.eval_noise;
+        self.noise_seed = fastrand::u64(..);
+
+        // Whatever happens, have a legal move to play
+        let legal_moves = position.legal_moves();
+        let mut result = SearchResult {
+            best_move: legal_moves.first().copied(),
+            score: 0,
+            depth: 0,
+            nodes: 0,
+        };
+
+        for depth in 1..=limits.max_depth.max(1) {
+            self.root_best = None;
+            let score = self.negamax(position, depth, 0, -INFINITY, INFINITY);
+            if self.stopped {
+                break;
+            }
+
+            if let Some((best_move, _)) = self.root_best {
+                result.best_move = Some(best_move);
+            }
+            result.score = score;
+            result.depth = depth;
+            result.nodes = self.nodes;
+            report(&result, &self.principal_variation(position, depth));
+
+            // No point searching deeper once a forced mate is found
+            if score.abs() > MATE_THRESHOLD {
+                break;
+            }
+        }
+
+        // Weaker levels now and then throw the game away on purpose
+        if !legal_moves.is_empty() && fastrand::f64() < limits.blunder_chance {
+            result.best_move = Some(legal_moves[fastrand::usize(..legal_moves.len())]);
+        }
+
+        result.nodes = self.nodes;
+        result
+    }
+
+    fn negamax(&mut self, position: &mut Position, depth: u32, ply: u32, mut alpha: i32, beta: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        self.nodes += 1;
+
+        // A repeated position is as good as a draw, no need to wait for the third time
+        if ply > 0 && (position.halfmove_clock() >= 100 || position.repetition_count() >= 2 || position.has_insufficient_material()) {
+            return 0;
+        }
+
+        let hash = position.hash();
+        let entry = self.probe(hash);
+        if let Some(entry) = entry
+            && ply > 0
+            && entry.depth >= depth
+        {
+            let score = score_from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => {}
+            }
+        }
+
+        if depth == 0 {
+            return self.quiescence(position, alpha, beta);
+        }
+
+        let mut moves = position.legal_moves();
+        if moves.is_empty() {
+            return if position.is_in_check() { -MATE_SCORE + ply as i32 } else { 0 };
+        }
+
+        order_moves(position, &mut moves, entry.and_then(|entry| entry.best_move));
+
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        for mv in moves {
+            position.make_move(mv);
+            let score = -self.negamax(position, depth - 1, ply + 1, -beta, -alpha);
+            position.unmake_move();
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+                if ply == 0 {
+                    self.root_best = Some((mv, score));
+                }
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.store(TableEntry { hash, depth, score: score_to_table(best_score, ply), bound, best_move });
+
+        best_score
+    }
+
+    /// Only captures and promotions are searched, so the evaluation is never
+    /// taken in the middle of an exchange.
+    fn quiescence(&mut self, position: &mut Position, mut alpha: i32, beta: i32) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        self.nodes += 1;
+
+        let stand_pat = self.evaluate(position);
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves: Vec<Move> = position.legal_moves().into_iter()
+            .filter(|mv| is_capture(position, *mv) || mv.promotion.is_some())
+            .collect();
+        order_moves(position, &mut moves, None);
+
+        for mv in moves {
+            position.make_move(mv);
+            let score = -self.quiescence(position, -beta, -alpha);
+            position.unmake_move();
+
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+
+    /// `evaluate`, give or take the configured noise. The noise is derived from the
+    /// position so a transposition scores the same however it was reached.
+    fn evaluate(&self, position: &Position) -> i32 {
+        let score = evaluate(position);
+        if self.eval_noise <= 0 {
+            return score;
+        }
+
+        let spread = 2 * self.eval_noise as u64 + 1;
+        let noise = ((position.hash() ^ self.noise_seed).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % spread;
+        score + noise as i32 - self.eval_noise
+    }
+
+    /// Best moves from the transposition table, followed from `position` for at most `depth` plies.
+    pub fn principal_variation(&self, position: &Position, depth: u32) -> Vec<Move> {
+        let mut position = position.clone();
+        let mut line = Vec::new();
+        while line.len() < depth as usize {
+            let Some(mv) = self.probe(position.hash()).and_then(|entry| entry.best_move) else { break };
+            // A hash collision could hand back a move from some other position
+            if !position.legal_moves().contains(&mv) {
+                break;
+            }
+            position.make_move(mv);
+            line.push(mv);
+        }
+        line
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        if !self.stopped && self.nodes.is_multiple_of(TIME_CHECK_INTERVAL)
+            && (Instant::now() >= self.deadline || self.stop_flag.load(Ordering::Relaxed)) {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    fn probe(&self, hash: u64) -> Option<TableEntry> {
+        self.table[hash as usize % TABLE_SIZE].filter(|entry| entry.hash == hash)
+    }
+
+    fn store(&mut self, entry: TableEntry) {
+        let slot = &mut self.table[entry.hash as usize % TABLE_SIZE];
+        // Keep the deeper result when two positions share a slot
+        if slot.is_none_or(|existing| existing.hash != entry.hash || existing.depth <= entry.depth) {
+            *slot = Some(entry);
+        }
+    }
+}
+
+/// Mate scores count plies from the root; the table stores them counted from the node instead.
+fn score_to_table(score: i32, ply: u32) -> i32 {
+    if score > MATE_THRESHOLD {
+        score + ply as i32
+    } else if score < -MATE_THRESHOLD {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: u32) -> i32 {
+    if score > MATE_THRESHOLD {
+        score - ply as i32
+    } else if score < -MATE_THRESHOLD {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+fn is_capture(position: &Position, mv: Move) -> bool {
+    mv.kind == MoveKind::EnPassant || position.piece_at(mv.to).is_some()
+}
+
+/// Best move from the transposition table first, then captures of valuable
+/// pieces by cheap ones (MVV-LVA), then promotions, then the quiet moves.
+fn order_moves(position: &Position, moves: &mut [Move], table_move: Option<Move>) {
+    moves.sort_by_cached_key(|&mv| {
+        if Some(mv) == table_move {
+            return i32::MIN;
+        }
+
+        let mut priority = 0;
+        if is_capture(position, mv) {
+            let victim = position.piece_at(mv.capture_square()).map_or(PieceType::Pawn, |piece| piece.piece_type);
+            let attacker = position.piece_at(mv.from).map_or(PieceType::Pawn, |piece| piece.piece_type);
+            priority -= 10 * piece_value(victim) - piece_value(attacker);
+        }
+        if let Some(promotion) = mv.promotion {
+            priority -= piece_value(promotion);
+        }
+        priority
+    });
+}
+
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King | PieceType::Empty => 0,
+    }
+}
+
+// Piece-square tables from white's side, rank 8 first so they read like a board diagram
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+/// Static evaluation in centipawns from the side to move's point of view:
+/// material plus a bonus or penalty for where each piece stands.
+pub fn evaluate(position: &Position) -> i32 {
+    let mut score = 0;
+    for square in Square::all() {
+        let Some(piece) = position.piece_at(square) else { continue };
+
+        // Black reads the tables mirrored top to bottom
+        let table_index = match piece.color {
+            PieceColor::White => (7 - square.row()) * 8 + square.col(),
+            PieceColor::Black => square.row() * 8 + square.col(),
+        };
+        let placement = match piece.piece_type {
+            PieceType::Pawn => PAWN_TABLE[table_index],
+            PieceType::Knight => KNIGHT_TABLE[table_index],
+            PieceType::Bishop => BISHOP_TABLE[table_index],
+            PieceType::Rook => ROOK_TABLE[table_index],
+            PieceType::Queen => QUEEN_TABLE[table_index],
+            PieceType::King => KING_TABLE[table_index],
+            PieceType::Empty => 0,
+        };
+
+        let value = piece_value(piece.piece_type) + placement;
+        score += if piece.color == position.side_to_move() { value } else { -value };
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(fen: &str, depth: u32) -> SearchResult {
+        let mut position = Position::from_fen(fen).unwrap();
+        Searcher::new().search(&mut position, depth_limit(depth))
+    }
+
+    fn depth_limit(depth: u32) -> SearchLimits {
+        SearchLimits { max_depth: depth, ..SearchLimits::timed(Duration::from_secs(60)) }
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let result = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+        let mv = result.best_move.unwrap();
+        assert_eq!((mv.from, mv.to), (Square::new(0, 0), Square::new(7, 0)));
+        assert!(result.score > MATE_THRESHOLD);
+    }
+
+    #[test]
+    fn moves_an_attacked_queen_to_safety() {
+        // The pawn on d5 attacks the queen and is defended, so taking it loses the queen too
+        let result = search("4k3/8/2p5/3p4/4Q3/8/8/4K3 w - - 0 1", 3);
+        let mv = result.best_move.unwrap();
+        assert_eq!(mv.from, Square::new(3, 4));
+        assert_ne!(mv.to, Square::new(4, 3));
+        assert!(result.score > 500, "score {}", result.score);
+    }
+
+    #[test]
+    fn does_not_grab_a_poisoned_pawn() {
+        // Qxb7 wins a pawn but the rook on b8 takes the queen back
+        let result = search("1r2k3/1p6/8/8/8/8/8/1Q2K3 w - - 0 1", 2);
+        let mv = result.best_move.unwrap();
+        assert_ne!((mv.from, mv.to), (Square::new(0, 1), Square::new(6, 1)));
+    }
+
+    #[test]
+    fn leaves_the_position_as_it_was() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut position = Position::from_fen(fen).unwrap();
+        let hash = position.hash();
+        Searcher::new().search(&mut position, depth_limit(3));
+        assert_eq!((position.to_fen(), position.hash()), (fen.to_string(), hash));
+    }
+
+    #[test]
+    fn reused_searcher_benefits_from_its_table() {
+        let mut position = Position::starting();
+        let mut searcher = Searcher::new();
+        let first = searcher.search(&mut position, depth_limit(5));
+        let second = searcher.search(&mut position, depth_limit(5));
+        assert!(second.nodes < first.nodes, "{} then {}", first.nodes, second.nodes);
+        assert_eq!(second.best_move, first.best_move);
+    }
+}
@@ -0,0 +1,711 @@
+//! Decoding single Syzygy table files, used by `tablebase.rs`.
+//!
+//! A table stores one value for every position with its material. Positions are numbered
+//! after the board's symmetries have moved the leading pieces (or the leading pawn's file)
+//! into a corner, and the values are compressed by recursive pairing: the commonest pair
+//! of neighbouring symbols becomes a new symbol, again and again, and the result is
+//! Huffman coded in fixed-size blocks found through a sparse index.
+//!
+//! Values read here are what the table holds. En passant, and positions where a capture
+//! beats the stored value, are resolved in `tablebase.rs` before these are trusted.
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::bitboard::{king_attacks, Bitboard};
+use crate::rules::*;
+use crate::tablebase::Wdl;
+
+// Flags of each subtable
+const STM: u8 = 1; // DTZ tables: black to move is stored rather than white
+const MAPPED: u8 = 2; // DTZ tables: values are looked up in a map
+const WIN_PLIES: u8 = 4; // DTZ tables: wins are stored in plies rather than full moves
+const LOSS_PLIES: u8 = 8; // likewise for losses
+const WIDE_MAP: u8 = 16; // DTZ tables: the map holds 16-bit values
+const SINGLE_VALUE: u8 = 128; // every position has the same value
+
+const NONE: usize = usize::MAX;
+
+/// A file is shorter than its header says, or is not the table its name promises.
+#[derive(Debug)]
+pub struct Malformed;
+
+type Result<T> = std::result::Result<T, Malformed>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableKind {
+    Wdl, // `.rtbw`
+    Dtz, // `.rtbz`
+}
+
+/// Lookup tables for turning piece squares into a position's number.
+struct IndexTables {
+    triangle: [usize; 64], // a1-d1-d4 triangle to 0..10, squares below the diagonal first
+    lower: [usize; 64],    // squares below the a1-h8 diagonal to 0..28
+    kings: [[u64; 64]; 10], // the 462 ways to place two kings, the first in the triangle
+    map_pawns: [usize; 64], // a2-h7 to 0..48, highest for the pawn that leads
+    lead_pawn_index: [[u64; 64]; 6], // by number of leading pawns and the leading one's square
+    lead_pawns_size: [[u64; 4]; 6], // by number of leading pawns and the leading one's file
+}
+
+fn index_tables() -> &'static IndexTables {
+    static TABLES: OnceLock<IndexTables> = OnceLock::new();
+    TABLES.get_or_init(IndexTables::new)
+}
+
+impl IndexTables {
+    fn new() -> Self {
+        let mut lower = [NONE; 64];
+        for (code, square) in (0..64).filter(|&square| diagonal_offset(square) < 0).enumerate() {
+            lower[square] = code;
+        }
+
+        let mut triangle = [NONE; 64];
+        let below = (0..64).filter(|&square| diagonal_offset(square) < 0 && square % 8 < 4 && square / 8 < 4);
+        let on = (0..64).filter(|&square| diagonal_offset(square) == 0 && square % 8 < 4);
+        for (code, square) in below.chain(on).enumerate() {
+            triangle[square] = code;
+        }
+
+        // Kings next to each other are left out, and when the first king is on the diagonal
+        // the second stays on or below it. Both on the diagonal are numbered last.
+        let mut kings = [[u64::MAX; 64]; 10];
+        let mut both_on_diagonal = Vec::new();
+        let mut code = 0;
+        for (index, row) in kings.iter_mut().enumerate() {
+            let first = (0..64).find(|&square| triangle[square] == index).unwrap();
+            let taken = king_attacks(Square::from_index(first)) | Bitboard::from_square(Square::from_index(first));
+            for (second, number) in row.iter_mut().enumerate() {
+                if taken.contains(Square::from_index(second)) || (diagonal_offset(first) == 0 && diagonal_offset(second) > 0) {
+                    continue;
+                }
+                if diagonal_offset(first) == 0 && diagonal_offset(second) == 0 {
+                    both_on_diagonal.push((index, second));
+                } else {
+                    *number = code;
+                    code += 1;
+                }
+            }
+        }
+        for (index, second) in both_on_diagonal {
+            kings[index][second] = code;
+            code += 1;
+        }
+        debug_assert_eq!(code, 462);
+
+        // The leading pawn is the one nearest the a- or h-file, the lowest among those;
+        // the other pawns can only stand on squares numbered below it.
+        let mut map_pawns = [NONE; 64];
+        let mut lead_pawn_index = [[0; 64]; 6];
+        let mut lead_pawns_size = [[0; 4]; 6];
+        let mut available = 48;
+        for lead_pawns in 1..6 {
+            for (file, size) in lead_pawns_size[lead_pawns].iter_mut().enumerate() {
+                let mut index = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        available -= 1;
+                        map_pawns[square] = available;
+                        available -= 1;
+                        map_pawns[flip_file(square)] = available;
+                    }
+                    lead_pawn_index[lead_pawns][square] = index;
+                    index += binomial(map_pawns[square] as u64, lead_pawns as u64 - 1);
+                }
+                *size = index;
+            }
+        }
+
+        IndexTables { triangle, lower, kings, map_pawns, lead_pawn_index, lead_pawns_size }
+    }
+}
+
+/// One table file, read into memory.
+#[derive(Clone)]
+pub struct Table {
+    bytes: Vec<u8>,
+    kind: TableKind,
+    symmetric: bool, // both sides have the same material, `KRvKR`
+    has_pawns: bool,
+    both_have_pawns: bool,
+    subtables: Vec<Vec<Subtable>>, // by leading pawn file a-d (just one without pawns), then side to move
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("bytes", &self.bytes.len())
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How the positions of one side to move (and one leading pawn file) are numbered and stored.
+#[derive(Clone, Debug, Default)]
+struct Subtable {
+    pieces: Vec<BoardPiece>, // in the order they are numbered; white is the first side of the table's name
+    group_lens: Vec<usize>, // pieces numbered together, the leading group first
+    factors: Vec<u64>, // what each group's number is multiplied by, then the number of positions
+    flags: u8,
+    single_value: u16,
+    block_size: usize,
+    blocks: usize,
+    block_length_entries: usize, // one per block, plus padding so the sparse index never points past the end
+    span: u64, // positions between sparse index entries
+    min_symbol_len: usize,
+    lowest_symbols: usize, // offset of the first symbol of each code length
+    base: Vec<u64>, // smallest left-aligned code of each length
+    symbol_lens: Vec<u32>, // values a symbol stands for, less one
+    symbols: usize, // offset of the pair each symbol was made from
+    sparse_index: usize,
+    block_lengths: usize,
+    data: usize,
+    dtz_maps: [usize; 4], // for wins, losses, cursed wins and blessed losses
+}
+
+impl Table {
+    /// Parses the header of the table for `material`, named as in the file, `KQvKR`.
+    pub fn parse(bytes: Vec<u8>, material: &str, kind: TableKind) -> Result<Self> {
+        let (white, black) = material.split_once('v').ok_or(Malformed)?;
+        let piece_count = white.len() + black.len();
+        let has_pawns = material.contains('P');
+        let both_have_pawns = white.contains('P') && black.contains('P');
+        let symmetric = white == black;
+        // Without pawns a table numbers three pieces together when some piece other than a
+        // king is alone of its kind, and only the two kings otherwise
+        let unique_pieces = [white, black].iter()
+            .any(|side| side.chars().any(|letter| letter != 'K' && side.matches(letter).count() == 1));
+
+        let layout = byte(&bytes, 4)?;
+        if (layout & 2 != 0) != has_pawns || (layout & 1 != 0) == symmetric || piece_count > 7 {
+            return Err(Malformed);
+        }
+
+        let files = if has_pawns { 4 } else { 1 };
+        let sides = if kind == TableKind::Wdl && !symmetric { 2 } else { 1 };
+        let mut at = 5;
+        let mut subtables = Vec::new();
+        for file in 0..files {
+            let order = byte(&bytes, at)?;
+            let pawn_order = if both_have_pawns { byte(&bytes, at + 1)? } else { 0xFF };
+            at += 1 + both_have_pawns as usize;
+
+            let mut file_subtables = Vec::new();
+            for side in 0..sides {
+                let shift = 4 * side;
+                let pieces = (0..piece_count)
+                    .map(|k| piece_from_nibble((byte(&bytes, at + k)? >> shift) & 0xF))
+                    .collect::<Result<Vec<_>>>()?;
+                if side_names(&pieces) != (white.to_string(), black.to_string()) {
+                    return Err(Malformed);
+                }
+                let order = [(order >> shift) & 0xF, (pawn_order >> shift) & 0xF];
+                file_subtables.push(Subtable::new(pieces, order, file, has_pawns, both_have_pawns, unique_pieces)?);
+            }
+            at += piece_count;
+            subtables.push(file_subtables);
+        }
+        at += at & 1;
+
+        for subtable in subtables.iter_mut().flatten() {
+            at = subtable.read_sizes(&bytes, at, kind)?;
+        }
+
+        if kind == TableKind::Dtz {
+            for file_subtables in &mut subtables {
+                let subtable = &mut file_subtables[0];
+                if subtable.flags & MAPPED == 0 {
+                    continue;
+                }
+                if subtable.flags & WIDE_MAP != 0 {
+                    at += at & 1;
+                    for map in &mut subtable.dtz_maps {
+                        *map = at + 2;
+                        at += 2 + 2 * u16_le(&bytes, at)? as usize;
+                    }
+                } else {
+                    for map in &mut subtable.dtz_maps {
+                        *map = at + 1;
+                        at += 1 + byte(&bytes, at)? as usize;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for subtable in subtables.iter_mut().flatten() {
+            subtable.sparse_index = at;
+            at += 6 * subtable.sparse_entries();
+        }
+        for subtable in subtables.iter_mut().flatten() {
+            subtable.block_lengths = at;
+            at += 2 * subtable.block_length_entries;
+        }
+        for subtable in subtables.iter_mut().flatten() {
+            at = (at + 0x3F) & !0x3F;
+            subtable.data = at;
+            at += subtable.blocks * subtable.block_size;
+        }
+        if at > bytes.len() {
+            return Err(Malformed);
+        }
+
+        Ok(Table { bytes, kind, symmetric, has_pawns, both_have_pawns, subtables })
+    }
+
+    /// Win, draw or loss stored for the side to move. `colors_swapped` says the position's
+    /// white has the material of the table's black, `KRvKQ` looked up in `KQvKR`.
+    pub fn wdl(&self, position: &Position, colors_swapped: bool) -> Result<Wdl> {
+        let (subtable, index) = self.locate(position, colors_swapped)?.ok_or(Malformed)?;
+        match self.value(subtable, index)? {
+            0 => Ok(Wdl::Loss),
+            1 => Ok(Wdl::BlessedLoss),
+            2 => Ok(Wdl::Draw),
+            3 => Ok(Wdl::CursedWin),
+            4 => Ok(Wdl::Win),
+            _ => Err(Malformed),
+        }
+    }
+
+    /// Plies to the next capture or pawn move stored for a position known to be `wdl`,
+    /// rounded up to full moves where the table stores those. `None` when the table only
+    /// holds the other side to move.
+    pub fn dtz(&self, position: &Position, colors_swapped: bool, wdl: Wdl) -> Result<Option<u32>> {
+        let Some((subtable, index)) = self.locate(position, colors_swapped)? else {
+            return Ok(None);
+        };
+        let mut value = self.value(subtable, index)? as usize;
+        if subtable.flags & MAPPED != 0 {
+            let map = subtable.dtz_maps[match wdl {
+                Wdl::Win => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                Wdl::BlessedLoss => 3,
+                Wdl::Draw => return Ok(Some(0)),
+            }];
+            value = if subtable.flags & WIDE_MAP != 0 {
+                u16_le(&self.bytes, map + 2 * value)? as usize
+            } else {
+                byte(&self.bytes, map + value)? as usize
+            };
+        }
+
+        let in_plies = match wdl {
+            Wdl::Win => subtable.flags & WIN_PLIES != 0,
+            Wdl::Loss => subtable.flags & LOSS_PLIES != 0,
+            _ => false,
+        };
+        Ok(Some(if in_plies { value as u32 } else { 2 * value as u32 }))
+    }
+
+    /// The subtable holding `position` and the position's number in it.
+    fn locate(&self, position: &Position, colors_swapped: bool) -> Result<Option<(&Subtable, u64)>> {
+        let tables = index_tables();
+        let black_to_move = position.side_to_move() == PieceColor::Black;
+        // Tables are stored from white's side: flip the board when the colours are the other
+        // way round, or when black is to move with the same material on both sides
+        let flip = colors_swapped || (self.symmetric && black_to_move);
+        let color = |color: PieceColor| if flip { color.opposite() } else { color };
+        let relative = |square: Square| if flip { flip_rank(square.index()) } else { square.index() };
+
+        let mut squares = Vec::with_capacity(7);
+        let mut used = Bitboard::EMPTY;
+        let file = if self.has_pawns {
+            let lead = self.subtables[0][0].pieces[0];
+            let pawns = position.pieces(PieceType::Pawn, color(lead.color));
+            used = pawns;
+            squares.extend(pawns.squares().map(relative));
+            let leading = (0..squares.len()).max_by_key(|&i| tables.map_pawns[squares[i]]).ok_or(Malformed)?;
+            squares.swap(0, leading);
+            let file = squares[0] % 8;
+            file.min(7 - file)
+        } else {
+            0
+        };
+
+        let sides = &self.subtables[file];
+        let black_side = black_to_move != flip;
+        let subtable = &sides[if black_side { sides.len() - 1 } else { 0 }];
+        if self.kind == TableKind::Dtz
+            && (subtable.flags & STM != 0) != black_side
+            && (!self.symmetric || self.has_pawns)
+        {
+            return Ok(None);
+        }
+
+        let lead_pawns = squares.len();
+        if self.has_pawns && lead_pawns != subtable.group_lens[0] {
+            return Err(Malformed);
+        }
+        for piece in &subtable.pieces[lead_pawns..] {
+            let square = (position.pieces(piece.piece_type, color(piece.color)) & !used).first().ok_or(Malformed)?;
+            used |= Bitboard::from_square(square);
+            squares.push(relative(square));
+        }
+
+        if squares[0] % 8 > 3 {
+            squares.iter_mut().for_each(|square| *square = flip_file(*square));
+        }
+
+        let mut index = if self.has_pawns {
+            squares[1..lead_pawns].sort_by_key(|&square| tables.map_pawns[square]);
+            let mut index = tables.lead_pawn_index[lead_pawns][squares[0]];
+            for (i, &square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                index += binomial(tables.map_pawns[square] as u64, i as u64);
+            }
+            index
+        } else {
+            if squares[0] / 8 > 3 {
+                squares.iter_mut().for_each(|square| *square = flip_rank(*square));
+            }
+            // The first leading piece off the a1-h8 diagonal goes below it
+            if let Some(i) = (0..subtable.group_lens[0]).find(|&i| diagonal_offset(squares[i]) != 0)
+                && diagonal_offset(squares[i]) > 0
+            {
+                squares.iter_mut().for_each(|square| *square = flip_diagonal(*square));
+            }
+            if subtable.group_lens[0] == 3 {
+                three_piece_index(&squares)
+            } else {
+                tables.kings.get(tables.triangle[squares[0]]).map(|row| row[squares[1]]).filter(|&index| index != u64::MAX).ok_or(Malformed)?
+            }
+        };
+        index *= subtable.factors[0];
+
+        // Every later group: its squares in order, each counted without the squares before it
+        let mut start = subtable.group_lens[0];
+        let mut remaining_pawns = self.both_have_pawns; // the other side's pawns come next, on ranks 2 to 7
+        for (group, &len) in subtable.group_lens.iter().enumerate().skip(1) {
+            let (earlier, rest) = squares.split_at_mut(start);
+            let group_squares = &mut rest[..len];
+            group_squares.sort_unstable();
+            let mut group_index = 0;
+            for (i, &square) in group_squares.iter().enumerate() {
+                let below = earlier.iter().filter(|&&other| other < square).count();
+                let skipped = if remaining_pawns { 8 } else { 0 };
+                group_index += binomial((square - below - skipped) as u64, i as u64 + 1);
+            }
+            index += group_index * subtable.factors[group];
+            remaining_pawns = false;
+            start += len;
+        }
+
+        Ok(Some((subtable, index)))
+    }
+
+    /// Decompresses the value of position `index`.
+    fn value(&self, subtable: &Subtable, index: u64) -> Result<u16> {
+        if subtable.flags & SINGLE_VALUE != 0 {
+            return Ok(subtable.single_value);
+        }
+        let bytes = &self.bytes;
+
+        // The sparse index names a block and how far into it the value every `span` positions sits;
+        // walk from there to the block that holds `index`
+        let entry = subtable.sparse_index + 6 * (index / subtable.span) as usize;
+        let mut block = u32_le(bytes, entry)? as usize;
+        let mut offset = u16_le(bytes, entry + 4)? as i64 + (index % subtable.span) as i64 - (subtable.span / 2) as i64;
+        let block_values = |block: usize| u16_le(bytes, subtable.block_lengths + 2 * block).map(|length| length as i64 + 1);
+        while offset < 0 {
+            block = block.checked_sub(1).ok_or(Malformed)?;
+            offset += block_values(block)?;
+        }
+        while offset >= block_values(block)? {
+            offset -= block_values(block)?;
+            block += 1;
+        }
+
+        // Read Huffman codes until reaching the symbol that covers `offset`
+        let mut at = subtable.data + block * subtable.block_size;
+        let mut buffer = u64_be(bytes, at)?;
+        at += 8;
+        let mut buffered = 64;
+        let mut symbol;
+        loop {
+            let mut len = 0;
+            while buffer < *subtable.base.get(len).ok_or(Malformed)? {
+                len += 1;
+            }
+            let code = (buffer - subtable.base[len]).checked_shr((64 - len - subtable.min_symbol_len) as u32).unwrap_or(0);
+            symbol = code as usize + u16_le(bytes, subtable.lowest_symbols + 2 * len)? as usize;
+            let values = *subtable.symbol_lens.get(symbol).ok_or(Malformed)? as i64 + 1;
+            if offset < values {
+                break;
+            }
+            offset -= values;
+            len += subtable.min_symbol_len;
+            buffer <<= len;
+            buffered -= len;
+            if buffered <= 32 {
+                buffered += 32;
+                buffer |= (u32_be(bytes, at)? as u64) << (64 - buffered);
+                at += 4;
+            }
+        }
+
+        // Split the symbol back into the pairs it was made of
+        while subtable.symbol_lens[symbol] != 0 {
+            let (left, right) = symbol_pair(bytes, subtable.symbols, symbol)?;
+            let left_values = *subtable.symbol_lens.get(left).ok_or(Malformed)? as i64 + 1;
+            if offset < left_values {
+                symbol = left;
+            } else {
+                offset -= left_values;
+                symbol = right;
+            }
+        }
+        Ok(symbol_pair(bytes, subtable.symbols, symbol)?.0 as u16)
+    }
+}
+
+impl Subtable {
+    fn new(pieces: Vec<BoardPiece>, order: [u8; 2], file: usize, has_pawns: bool, both_have_pawns: bool, unique_pieces: bool) -> Result<Self> {
+        let tables = index_tables();
+        let first_len = if has_pawns { 0 } else if unique_pieces { 3 } else { 2 };
+        let mut group_lens = vec![1];
+        for i in 1..pieces.len() {
+            if i < first_len || pieces[i] == pieces[i - 1] {
+                *group_lens.last_mut().unwrap() += 1;
+            } else {
+                group_lens.push(1);
+            }
+        }
+        if (has_pawns && (pieces[0].piece_type != PieceType::Pawn || group_lens[0] > 5)) || (both_have_pawns && group_lens.len() < 2) {
+            return Err(Malformed);
+        }
+
+        // Groups are multiplied together in the order the header gives: `order` holds
+        // where the leading group and, with pawns on both sides, the other pawns go
+        let mut factors = vec![0; group_lens.len() + 1];
+        let mut next = if both_have_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - group_lens[0] - if both_have_pawns { group_lens[1] } else { 0 };
+        let mut size: u64 = 1;
+        let mut k = 0;
+        while next < group_lens.len() || k == order[0] || k == order[1] {
+            let positions = if k == order[0] {
+                factors[0] = size;
+                if has_pawns {
+                    tables.lead_pawns_size[group_lens[0]][file]
+                } else if unique_pieces {
+                    31_332
+                } else {
+                    462
+                }
+            } else if k == order[1] {
+                factors[1] = size;
+                binomial(48 - group_lens[0] as u64, group_lens[1] as u64)
+            } else {
+                factors[next] = size;
+                let positions = binomial(free_squares as u64, group_lens[next] as u64);
+                free_squares = free_squares.checked_sub(group_lens[next]).ok_or(Malformed)?;
+                next += 1;
+                positions
+            };
+            size = size.checked_mul(positions).ok_or(Malformed)?;
+            k += 1;
+        }
+        factors[group_lens.len()] = size;
+
+        Ok(Subtable { pieces, group_lens, factors, ..Default::default() })
+    }
+
+    /// Reads the block and Huffman code layout at `at`, returning where the next subtable's starts.
+    fn read_sizes(&mut self, bytes: &[u8], at: usize, kind: TableKind) -> Result<usize> {
+        self.flags = byte(bytes, at)?;
+        if self.flags & SINGLE_VALUE != 0 {
+            // DTZ tables with a single value only ever hold draws, whatever the byte says
+            self.single_value = if kind == TableKind::Wdl { byte(bytes, at + 1)? as u16 } else { 0 };
+            return Ok(at + 2);
+        }
+
+        self.block_size = 1usize.checked_shl(byte(bytes, at + 1)? as u32).ok_or(Malformed)?;
+        self.span = 1u64.checked_shl(byte(bytes, at + 2)? as u32).ok_or(Malformed)?;
+        self.blocks = u32_le(bytes, at + 4)? as usize;
+        self.block_length_entries = self.blocks + byte(bytes, at + 3)? as usize;
+        let max_symbol_len = byte(bytes, at + 8)? as usize;
+        self.min_symbol_len = byte(bytes, at + 9)? as usize;
+        if self.min_symbol_len == 0 || max_symbol_len < self.min_symbol_len || max_symbol_len > 32 {
+            return Err(Malformed);
+        }
+
+        // Canonical Huffman code: longer codes have lower values. Aligned to the left of a
+        // 64-bit buffer, a code of length `len` is at least `base[len]` and below `base[len - 1]`.
+        self.lowest_symbols = at + 10;
+        let lens = max_symbol_len - self.min_symbol_len + 1;
+        self.base = vec![0; lens];
+        for i in (0..lens - 1).rev() {
+            let lowest = u16_le(bytes, self.lowest_symbols + 2 * i)? as u64;
+            let next_lowest = u16_le(bytes, self.lowest_symbols + 2 * (i + 1))? as u64;
+            self.base[i] = (self.base[i + 1] + lowest).checked_sub(next_lowest).ok_or(Malformed)? / 2;
+        }
+        for (i, base) in self.base.iter_mut().enumerate() {
+            *base = base.checked_shl((64 - self.min_symbol_len - i) as u32).unwrap_or(0);
+        }
+
+        let mut at = self.lowest_symbols + 2 * lens;
+        let symbol_count = u16_le(bytes, at)? as usize;
+        at += 2;
+        self.symbols = at;
+        self.symbol_lens = vec![0; symbol_count];
+        let mut visited = vec![false; symbol_count];
+        for symbol in 0..symbol_count {
+            self.count_symbol(bytes, symbol, &mut visited)?;
+        }
+        Ok(at + 3 * symbol_count + (symbol_count & 1))
+    }
+
+    /// Fills in how many values `symbol` expands to, less one, doing its pair's symbols first.
+    fn count_symbol(&mut self, bytes: &[u8], symbol: usize, visited: &mut [bool]) -> Result<()> {
+        if *visited.get(symbol).ok_or(Malformed)? {
+            return Ok(());
+        }
+        visited[symbol] = true;
+        let (left, right) = symbol_pair(bytes, self.symbols, symbol)?;
+        if right == 0xFFF {
+            return Ok(()); // a value of its own
+        }
+        self.count_symbol(bytes, left, visited)?;
+        self.count_symbol(bytes, right, visited)?;
+        self.symbol_lens[symbol] = self.symbol_lens[left] + self.symbol_lens[right] + 1;
+        Ok(())
+    }
+
+    fn sparse_entries(&self) -> usize {
+        if self.flags & SINGLE_VALUE != 0 {
+            return 0;
+        }
+        self.factors.last().unwrap().div_ceil(self.span) as usize
+    }
+}
+
+/// Number of the three leading pieces of a pawnless table, the first in the a1-d1-d4 triangle.
+fn three_piece_index(squares: &[usize]) -> u64 {
+    let tables = index_tables();
+    let (first, second, third) = (squares[0], squares[1], squares[2]);
+    let adjust1 = (second > first) as usize;
+    let adjust2 = (third > first) as usize + (third > second) as usize;
+    let rank = |square: usize| square / 8;
+    let index = if diagonal_offset(first) != 0 {
+        (tables.triangle[first] * 63 + second - adjust1) * 62 + third - adjust2
+    } else if diagonal_offset(second) != 0 {
+        (6 * 63 + rank(first) * 28 + tables.lower[second]) * 62 + third - adjust2
+    } else if diagonal_offset(third) != 0 {
+        6 * 63 * 62 + 4 * 28 * 62 + rank(first) * 7 * 28 + (rank(second) - adjust1) * 28 + tables.lower[third]
+    } else {
+        6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(first) * 7 * 6 + (rank(second) - adjust1) * 6 + rank(third) - adjust2
+    };
+    index as u64
+}
+
+/// Header nibbles: 1 to 6 for pawn to king, plus 8 for black.
+fn piece_from_nibble(nibble: u8) -> Result<BoardPiece> {
+    let piece_type = match nibble & 7 {
+        1 => PieceType::Pawn,
+        2 => PieceType::Knight,
+        3 => PieceType::Bishop,
+        4 => PieceType::Rook,
+        5 => PieceType::Queen,
+        6 => PieceType::King,
+        _ => return Err(Malformed),
+    };
+    let color = if nibble & 8 == 0 { PieceColor::White } else { PieceColor::Black };
+    Ok(BoardPiece::new(piece_type, color))
+}
+
+/// Each side's pieces named as in a file name, `("KQ", "KR")`.
+fn side_names(pieces: &[BoardPiece]) -> (String, String) {
+    let name = |color: PieceColor| -> String {
+        let order = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+        order.iter().flat_map(|&piece_type| {
+            let count = pieces.iter().filter(|&&piece| piece == BoardPiece::new(piece_type, color)).count();
+            std::iter::repeat_n(crate::notation::piece_letter(piece_type).unwrap_or('P'), count)
+        }).collect()
+    };
+    (name(PieceColor::White), name(PieceColor::Black))
+}
+
+/// The two symbols `symbol` was made from, packed as 12-bit numbers in three bytes.
+/// A right half of 0xFFF marks a plain value, held in the left half.
+fn symbol_pair(bytes: &[u8], symbols: usize, symbol: usize) -> Result<(usize, usize)> {
+    let at = symbols + 3 * symbol;
+    let (low, middle, high) = (byte(bytes, at)? as usize, byte(bytes, at + 1)? as usize, byte(bytes, at + 2)? as usize);
+    Ok((((middle & 0xF) << 8) | low, (high << 4) | (middle >> 4)))
+}
+
+fn binomial(n: u64, k: u64) -> u64 {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |result, i| result * (n - i) / (i + 1))
+}
+
+/// Ranks above files are positive: above the a1-h8 diagonal.
+fn diagonal_offset(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+fn flip_file(square: usize) -> usize {
+    square ^ 7
+}
+
+fn flip_rank(square: usize) -> usize {
+    square ^ 56
+}
+
+fn flip_diagonal(square: usize) -> usize {
+    ((square >> 3) | (square << 3)) & 63
+}
+
+fn byte(bytes: &[u8], at: usize) -> Result<u8> {
+    bytes.get(at).copied().ok_or(Malformed)
+}
+
+fn array<const N: usize>(bytes: &[u8], at: usize) -> Result<[u8; N]> {
+    bytes.get(at..at + N).ok_or(Malformed)?.try_into().map_err(|_| Malformed)
+}
+
+fn u16_le(bytes: &[u8], at: usize) -> Result<u16> {
+    array(bytes, at).map(u16::from_le_bytes)
+}
+
+fn u32_le(bytes: &[u8], at: usize) -> Result<u32> {
+    array(bytes, at).map(u32::from_le_bytes)
+}
+
+fn u32_be(bytes: &[u8], at: usize) -> Result<u32> {
+    array(bytes, at).map(u32::from_be_bytes)
+}
+
+fn u64_be(bytes: &[u8], at: usize) -> Result<u64> {
+    array(bytes, at).map(u64::from_be_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_every_placement_of_two_kings() {
+        let tables = index_tables();
+        let mut numbers: Vec<u64> = tables.kings.iter().flatten().copied().filter(|&number| number != u64::MAX).collect();
+        numbers.sort_unstable();
+        assert_eq!(numbers, (0..462).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn leading_pawns_are_numbered_from_the_edge() {
+        let tables = index_tables();
+        let square = |name: &str| name.parse::<Square>().unwrap().index();
+        assert_eq!((tables.map_pawns[square("a2")], tables.map_pawns[square("h2")]), (47, 46));
+        assert_eq!((tables.map_pawns[square("d7")], tables.map_pawns[square("e7")]), (1, 0));
+        // A lone leading pawn can stand on six ranks of its file
+        assert_eq!(tables.lead_pawns_size[1], [6; 4]);
+    }
+
+    #[test]
+    fn rejects_files_that_do_not_match_their_name() {
+        assert!(Table::parse(vec![0x71, 0xE8, 0x23, 0x5D], "KQvK", TableKind::Wdl).is_err());
+        assert!(Table::parse(vec![0x71, 0xE8, 0x23, 0x5D, 0x02, 0, 0, 0], "KQvK", TableKind::Wdl).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+//! Syzygy endgame tablebases: `.rtbw` files hold win/draw/loss, `.rtbz` files the
+//! distance to the next capture or pawn move (DTZ) that keeps the result.
+//!
+//! Files are found by material, `KQvKR.rtbw` for king and queen against king and rook,
+//! and their headers are checked when the directory is opened; files that are not
+//! Syzygy tables are skipped and listed by [`Tablebases::skipped`]. A table is read into
+//! memory the first time a position needs it and decoded by `syzygy.rs`.
+//!
+//! Tables leave out what a short search settles: positions with en passant rights, and
+//! positions where a capture is at least as good as anything else. Probing tries the
+//! captures first and only trusts the stored value when none of them does better.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::rules::*;
+use crate::syzygy::{Table, TableKind};
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+/// Syzygy tables only exist up to seven pieces, kings included.
+pub const MAX_TABLEBASE_PIECES: usize = 7;
+
+#[derive(Debug)]
+pub enum TablebaseError {
+    Io(io::Error),
+    NotCovered, // too many pieces, castling rights left, or no file for the material
+    Unreadable(String), // a table file that could not be read or decoded, and why
+}
+
+impl fmt::Display for TablebaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TablebaseError::Io(error) => write!(f, "{error}"),
+            TablebaseError::NotCovered => write!(f, "position is not in the tablebases"),
+            TablebaseError::Unreadable(reason) => write!(f, "cannot use {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for TablebaseError {}
+
+impl From<io::Error> for TablebaseError {
+    fn from(error: io::Error) -> Self {
+        TablebaseError::Io(error)
+    }
+}
+
+/// Result with perfect play, from the side to move's point of view. Cursed wins and
+/// blessed losses are wins and losses the fifty-move rule turns into draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    pub fn opposite(self) -> Self {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+
+    /// DTZ of a position whose best move is a capture or pawn move, counted with the
+    /// sign of the result: that move itself is the one ply left.
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            Wdl::Loss => -1,
+            Wdl::BlessedLoss => -101,
+            Wdl::Draw => 0,
+            Wdl::CursedWin => 101,
+            Wdl::Win => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TablebaseResult {
+    pub wdl: Wdl,
+    // Plies until the next capture or pawn move on the way to that result, over 100 for
+    // cursed wins and blessed losses. Tables that store full moves round it up by one.
+    pub dtz: u32,
+}
+
+#[derive(Debug, Default)]
+struct TableFiles {
+    wdl: Option<TableFile>,
+    dtz: Option<TableFile>,
+}
+
+#[derive(Debug)]
+struct TableFile {
+    path: PathBuf,
+    table: OnceLock<Result<Table, String>>, // filled by the first probe that needs it
+}
+
+impl TableFile {
+    fn table(&self, material: &str, kind: TableKind) -> Result<&Table, TablebaseError> {
+        let table = self.table.get_or_init(|| {
+            let bytes = std::fs::read(&self.path).map_err(|error| error.to_string())?;
+            Table::parse(bytes, material, kind).map_err(|_| "not a valid table".to_string())
+        });
+        table.as_ref().map_err(|reason| self.unreadable(reason))
+    }
+
+    fn unreadable(&self, reason: &str) -> TablebaseError {
+        TablebaseError::Unreadable(format!("{}: {reason}", self.path.display()))
+    }
+}
+
+/// The tables found in one directory.
+#[derive(Debug, Default)]
+pub struct Tablebases {
+    tables: HashMap<String, TableFiles>, // by material, as in the file name
+    max_pieces: usize,
+    skipped: Vec<PathBuf>, // table files whose header was wrong or could not be read
+}
+
+impl Tablebases {
+    /// Indexes every `.rtbw`/`.rtbz` file in `directory`, checking each one's header.
+    /// Only a directory that cannot be listed is an error; bad files are skipped.
+    pub fn open(directory: &Path) -> Result<Self, TablebaseError> {
+        let mut tablebases = Self::default();
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            let (Some(name), Some(extension)) = (path.file_stem().and_then(|n| n.to_str()), path.extension().and_then(|e| e.to_str())) else {
+                continue;
+            };
+            let magic = match extension {
+                "rtbw" => WDL_MAGIC,
+                "rtbz" => DTZ_MAGIC,
+                _ => continue,
+            };
+
+            let mut header = [0; 4];
+            let read = std::fs::File::open(&path).and_then(|mut file| file.read_exact(&mut header));
+            if read.is_err() || header != magic {
+                tablebases.skipped.push(path);
+                continue;
+            }
+
+            let pieces = name.chars().filter(|c| c.is_ascii_uppercase()).count();
+            tablebases.max_pieces = tablebases.max_pieces.max(pieces);
+            let files = tablebases.tables.entry(name.to_string()).or_default();
+            let slot = if extension == "rtbw" { &mut files.wdl } else { &mut files.dtz };
+            *slot = Some(TableFile { path, table: OnceLock::new() });
+        }
+        Ok(tablebases)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Files that looked like tables by name but were not, and were left out.
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    /// Most pieces, kings included, of any table found.
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    /// Whether a table for `position`'s material is present. Positions where castling
+    /// is still possible are never in the tablebases.
+    pub fn covers(&self, position: &Position) -> bool {
+        self.table_for(position).is_some()
+    }
+
+    /// The table for `position`'s material, and whether it is stored with the colours the
+    /// other way round: `KRvKQ` is found in `KQvKR`.
+    fn table_for(&self, position: &Position) -> Option<(&String, &TableFiles, bool)> {
+        let castling = position.castling_rights();
+        if castling != CastlingRights::NONE || piece_count(position) > self.max_pieces {
+            return None;
+        }
+        let (white, black) = material_names(position);
+        if let Some((name, files)) = self.tables.get_key_value(&format!("{white}v{black}")) {
+            return Some((name, files, false));
+        }
+        self.tables.get_key_value(&format!("{black}v{white}")).map(|(name, files)| (name, files, true))
+    }
+
+    /// Win, draw or loss for the side to move, as it stands straight after a capture
+    /// or pawn move: the halfmove clock is not taken into account.
+    pub fn probe_wdl(&self, position: &Position) -> Result<Wdl, TablebaseError> {
+        if !self.covers(position) {
+            return Err(TablebaseError::NotCovered);
+        }
+        Ok(self.search(&mut position.clone(), false)?.0)
+    }
+
+    /// Result and distance to zeroing for the side to move.
+    pub fn probe(&self, position: &Position) -> Result<TablebaseResult, TablebaseError> {
+        if !self.covers(position) {
+            return Err(TablebaseError::NotCovered);
+        }
+        let (wdl, dtz) = self.dtz(&mut position.clone())?;
+        Ok(TablebaseResult { wdl, dtz: dtz.unsigned_abs() })
+    }
+
+    /// The move that keeps the best result for the side to move, with that result.
+    /// Every reply is probed, so captures into smaller tables are handled too.
+    pub fn best_move(&self, position: &Position) -> Result<Option<(Move, TablebaseResult)>, TablebaseError> {
+        if !self.covers(position) {
+            return Err(TablebaseError::NotCovered);
+        }
+
+        let mut position = position.clone();
+        let mut results = Vec::new();
+        for mv in position.legal_moves() {
+            let zeroing = is_zeroing(&position, mv);
+            position.make_move(mv);
+            let game_over = position.legal_moves().is_empty();
+            let reply = if game_over {
+                // Mate or stalemate on the board, nothing to look up
+                Ok(if position.is_in_check() { (Wdl::Loss, -1) } else { (Wdl::Draw, 0) })
+            } else if zeroing {
+                self.search(&mut position, false).map(|(wdl, _)| (wdl, wdl.dtz_before_zeroing()))
+            } else {
+                self.dtz(&mut position)
+            };
+            position.unmake_move();
+
+            // The reply's DTZ counts from the opponent's side. Seen from ours it has the other
+            // sign, and our move adds a ply unless it zeroes the count or ends the game
+            let (reply_wdl, reply_dtz) = reply?;
+            let dtz = if zeroing || game_over { -reply_dtz } else { -reply_dtz - reply_dtz.signum() };
+            results.push((mv, TablebaseResult { wdl: reply_wdl.opposite(), dtz: dtz.unsigned_abs() }));
+        }
+        Ok(best_result(results))
+    }
+
+    /// WDL of `position` after trying its captures, and its pawn moves too when `pawn_moves`
+    /// is set. The flag returned says the best move is one of those, so the position's
+    /// DTZ is one ply and its table entry may hold anything.
+    fn search(&self, position: &mut Position, pawn_moves: bool) -> Result<(Wdl, bool), TablebaseError> {
+        let moves = position.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for &mv in &moves {
+            let pawn_move = position.piece_at(mv.from).is_some_and(|piece| piece.piece_type == PieceType::Pawn);
+            if position.piece_at(mv.capture_square()).is_none() && !(pawn_moves && pawn_move) {
+                continue;
+            }
+            searched += 1;
+            position.make_move(mv);
+            let value = self.search(position, false);
+            position.unmake_move();
+
+            let value = value?.0.opposite();
+            if value == Wdl::Win {
+                return Ok((value, true));
+            }
+            best = best.max(value);
+        }
+
+        // With every move tried the table is not needed, and could be wrong: it knows
+        // nothing of en passant, which may be the only way out of stalemate
+        let no_other_moves = searched > 0 && searched == moves.len();
+        let stored = if no_other_moves { best } else { self.table_wdl(position)? };
+        if best >= stored {
+            return Ok((best, best > Wdl::Draw || no_other_moves));
+        }
+        Ok((stored, false))
+    }
+
+    /// WDL and DTZ of `position`, the DTZ positive when the side to move wins.
+    fn dtz(&self, position: &mut Position) -> Result<(Wdl, i32), TablebaseError> {
+        let (wdl, zeroing) = self.search(position, true)?;
+        if wdl == Wdl::Draw || zeroing {
+            return Ok((wdl, wdl.dtz_before_zeroing()));
+        }
+        if let Some(plies) = self.table_dtz(position, wdl)? {
+            return Ok((wdl, wdl.dtz_before_zeroing() + wdl.dtz_before_zeroing().signum() * plies as i32));
+        }
+
+        // The table only stores the other side to move: take the best reply's DTZ, one ply on
+        let mut best: Option<i32> = None;
+        for mv in position.legal_moves() {
+            let zeroing = is_zeroing(position, mv);
+            position.make_move(mv);
+            let reply = if zeroing {
+                self.search(position, false).map(|(reply_wdl, _)| reply_wdl.dtz_before_zeroing())
+            } else {
+                self.dtz(position).map(|(_, dtz)| dtz)
+            };
+            let mates = position.is_in_check() && position.legal_moves().is_empty();
+            position.unmake_move();
+
+            let reply = reply?;
+            let dtz = if mates {
+                1
+            } else if zeroing {
+                -reply
+            } else {
+                -reply - reply.signum()
+            };
+            if dtz.signum() == wdl.dtz_before_zeroing().signum() && best.is_none_or(|best| dtz < best) {
+                best = Some(dtz);
+            }
+        }
+        // No moves that keep the result means the side to move is mated
+        Ok((wdl, best.unwrap_or(-1)))
+    }
+
+    /// The WDL value stored for `position`, without looking at any moves.
+    fn table_wdl(&self, position: &Position) -> Result<Wdl, TablebaseError> {
+        if position.occupied().count() == 2 {
+            return Ok(Wdl::Draw); // bare kings have no table
+        }
+        let (name, files, colors_swapped) = self.table_for(position).ok_or(TablebaseError::NotCovered)?;
+        let file = files.wdl.as_ref().ok_or(TablebaseError::NotCovered)?;
+        file.table(name, TableKind::Wdl)?
+            .wdl(position, colors_swapped)
+            .map_err(|_| file.unreadable("position not found in the table"))
+    }
+
+    /// The DTZ stored for `position`, known to be `wdl`, or `None` when its table only
+    /// has the other side to move.
+    fn table_dtz(&self, position: &Position, wdl: Wdl) -> Result<Option<u32>, TablebaseError> {
+        let (name, files, colors_swapped) = self.table_for(position).ok_or(TablebaseError::NotCovered)?;
+        let file = files.dtz.as_ref().ok_or(TablebaseError::NotCovered)?;
+        file.table(name, TableKind::Dtz)?
+            .dtz(position, colors_swapped, wdl)
+            .map_err(|_| file.unreadable("position not found in the table"))
+    }
+}
+
+/// Captures and pawn moves start the fifty-move count again.
+fn is_zeroing(position: &Position, mv: Move) -> bool {
+    position.piece_at(mv.capture_square()).is_some()
+        || position.piece_at(mv.from).is_some_and(|piece| piece.piece_type == PieceType::Pawn)
+}
+
+/// Best of the moves' results: the strongest outcome, reached as fast as possible
+/// when winning and put off as long as possible when losing.
+fn best_result(results: Vec<(Move, TablebaseResult)>) -> Option<(Move, TablebaseResult)> {
+    results.into_iter().max_by_key(|(_, result)| {
+        let distance = if result.wdl > Wdl::Draw { -(result.dtz as i64) } else { result.dtz as i64 };
+        (result.wdl, distance)
+    })
+}
+
+fn piece_count(position: &Position) -> usize {
+    Square::all().filter(|&square| position.piece_at(square).is_some()).count()
+}
+
+/// Each side's pieces in Syzygy's order, `KQRBNP`.
+fn material_names(position: &Position) -> (String, String) {
+    let name = |color: PieceColor| -> String {
+        let order = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+        order.iter().flat_map(|&piece_type| {
+            let count = Square::all()
+                .filter(|&square| position.piece_at(square) == Some(BoardPiece::new(piece_type, color)))
+                .count();
+            std::iter::repeat_n(crate::notation::piece_letter(piece_type).unwrap_or('P'), count)
+        }).collect()
+    };
+    (name(PieceColor::White), name(PieceColor::Black))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_files::TempDir;
+
+    fn directory_with(files: &[(&str, [u8; 4])]) -> TempDir {
+        let directory = TempDir::new("syzygy");
+        for (name, magic) in files {
+            std::fs::write(directory.path().join(name), magic).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn names_material_in_syzygy_order() {
+        let position = Position::from_fen("8/8/4k3/8/2p5/8/1NR5/K7 w - - 0 1").unwrap();
+        assert_eq!(material_names(&position), ("KRN".to_string(), "KP".to_string()));
+    }
+
+    #[test]
+    fn finds_tables_for_either_colour() {
+        let directory = directory_with(&[("KQvKR.rtbw", WDL_MAGIC), ("KQvKR.rtbz", DTZ_MAGIC), ("notes.txt", [0; 4])]);
+        let tablebases = Tablebases::open(directory.path()).unwrap();
+        assert_eq!((tablebases.len(), tablebases.max_pieces()), (1, 4));
+
+        let white_queen = Position::from_fen("8/8/4k3/8/8/8/1Q5r/K7 w - - 0 1").unwrap();
+        let black_queen = Position::from_fen("8/8/4k3/8/8/8/1R5q/K7 w - - 0 1").unwrap();
+        let other = Position::from_fen("8/8/4k3/8/8/8/1R5r/K7 w - - 0 1").unwrap();
+        assert!(tablebases.covers(&white_queen) && tablebases.covers(&black_queen));
+        assert!(!tablebases.covers(&other));
+        assert!(matches!(tablebases.probe_wdl(&other), Err(TablebaseError::NotCovered)));
+        assert!(!tablebases.covers(&Position::starting()));
+    }
+
+    #[test]
+    fn skips_files_with_the_wrong_magic() {
+        let directory = directory_with(&[("KQvK.rtbw", DTZ_MAGIC), ("KRvK.rtbw", WDL_MAGIC), ("KRvK.rtbz", DTZ_MAGIC)]);
+        std::fs::write(directory.path().join("KBvK.rtbz"), [0xD7]).unwrap(); // too short for a header
+        let tablebases = Tablebases::open(directory.path()).unwrap();
+
+        assert_eq!(tablebases.len(), 1);
+        let mut skipped: Vec<_> = tablebases.skipped().iter().filter_map(|path| path.file_name()?.to_str()).collect();
+        skipped.sort();
+        assert_eq!(skipped, ["KBvK.rtbz", "KQvK.rtbw"]);
+        assert!(tablebases.covers(&Position::from_fen("8/8/4k3/8/8/8/1R6/K7 w - - 0 1").unwrap()));
+        assert!(!tablebases.covers(&Position::from_fen("8/8/4k3/8/8/8/1Q6/K7 w - - 0 1").unwrap()));
+    }
+
+    #[test]
+    fn prefers_fast_wins_and_slow_losses() {
+        let position = Position::starting();
+        let moves = position.legal_moves();
+        let result = |wdl, dtz| TablebaseResult { wdl, dtz };
+
+        let winning = vec![(moves[0], result(Wdl::Win, 9)), (moves[1], result(Wdl::Win, 3)), (moves[2], result(Wdl::Draw, 0))];
+        assert_eq!(best_result(winning).unwrap().0, moves[1]);
+
+        let losing = vec![(moves[0], result(Wdl::Loss, 9)), (moves[1], result(Wdl::Loss, 3)), (moves[2], result(Wdl::BlessedLoss, 1))];
+        assert_eq!(best_result(losing).unwrap().0, moves[2]);
+    }
+
+    // Real 3- and 4-piece tables from tablebase.sesse.net, with reference results for
+    // positions they cover: WDL from -2 (loss) to 2 (win) and DTZ as the tables round it
+    fn real_tables() -> Tablebases {
+        Tablebases::open(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy"))).unwrap()
+    }
+
+    #[test]
+    fn matches_reference_results() {
+        let tablebases = real_tables();
+        let csv = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy/regular.csv")).unwrap();
+        for line in csv.lines().skip(1) {
+            let fields: Vec<_> = line.split(',').collect();
+            let position = Position::from_fen(&format!("{} 0 1", fields[0])).unwrap();
+            let (wdl, dtz): (i32, i32) = (fields[1].parse().unwrap(), fields[2].parse().unwrap());
+
+            let result = tablebases.probe(&position).unwrap();
+            assert_eq!((result.wdl as i32 - 2, result.dtz), (wdl, dtz.unsigned_abs()), "{line}");
+            assert_eq!(tablebases.probe_wdl(&position).unwrap(), result.wdl, "{line}");
+        }
+    }
+
+    #[test]
+    fn best_move_keeps_the_result() {
+        let tablebases = real_tables();
+        for fen in ["8/8/8/2R5/1K6/8/5k2/8 w - - 0 1", "8/3p4/5p2/2K5/8/8/8/2k5 b - - 0 1", "8/8/3k4/8/8/8/4P3/3K4 w - - 0 1"] {
+            let position = Position::from_fen(fen).unwrap();
+            let (mv, result) = tablebases.best_move(&position).unwrap().unwrap();
+            assert_eq!(result.wdl, tablebases.probe(&position).unwrap().wdl, "{fen}");
+
+            let mut after = position.clone();
+            after.make_move(mv);
+            assert_eq!(tablebases.probe(&after).unwrap().wdl, result.wdl.opposite(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn best_moves_mate_within_the_distance_probed() {
+        let tablebases = real_tables();
+        let mut position = Position::from_fen("8/8/8/4k3/8/8/8/1Q2K3 w - - 0 1").unwrap();
+        let start = tablebases.probe(&position).unwrap();
+        assert_eq!(start.wdl, Wdl::Win);
+
+        let mut plies = 0;
+        while let Some((mv, _)) = tablebases.best_move(&position).unwrap() {
+            position.make_move(mv);
+            plies += 1;
+            assert!(plies <= start.dtz, "no mate within {} plies", start.dtz);
+        }
+        assert_eq!(position.outcome(), Some(GameOutcome::Checkmate { winner: PieceColor::White }));
+    }
+
+    #[test]
+    fn reports_tables_that_cannot_be_decoded() {
+        let directory = directory_with(&[("KRvK.rtbw", WDL_MAGIC), ("KRvK.rtbz", DTZ_MAGIC)]);
+        let tablebases = Tablebases::open(directory.path()).unwrap();
+        let position = Position::from_fen("8/8/4k3/8/8/8/1R6/K7 w - - 0 1").unwrap();
+        assert!(matches!(tablebases.probe(&position), Err(TablebaseError::Unreadable(_))));
+    }
+}
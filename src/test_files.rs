@@ -0,0 +1,27 @@
+//! Scratch directories for tests that need real files.
+
+use std::path::{Path, PathBuf};
+
+/// A directory of its own under the system temp directory. It is deleted with
+/// everything in it when dropped, so a failing test does not leave it behind.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` tells which test the directory belongs to; the process id and a random
+    /// suffix keep tests that run at the same time apart.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("chess_bevy_{name}_{}_{:016x}", std::process::id(), fastrand::u64(..)));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,432 @@
+//! Client side of the Universal Chess Interface: runs an external engine as a
+//! subprocess and talks to it over its stdin/stdout.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::rules::Move;
+
+/// How long an engine gets to answer `uci` and `isready`.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum UciError {
+    Io(io::Error),
+    Timeout(&'static str), // the reply that never came
+    Exited,
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciError::Io(error) => write!(f, "{error}"),
+            UciError::Timeout(reply) => write!(f, "engine did not answer with '{reply}' in time"),
+            UciError::Exited => write!(f, "engine exited"),
+        }
+    }
+}
+
+impl std::error::Error for UciError {}
+
+impl From<io::Error> for UciError {
+    fn from(error: io::Error) -> Self {
+        UciError::Io(error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UciScore {
+    Centipawns(i32),
+    Mate(i32), // moves until mate, negative when the engine is getting mated
+}
+
+/// The parts of an `info` line the game shows.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UciInfo {
+    pub depth: Option<u32>,
+    pub score: Option<UciScore>,
+    pub nodes: Option<u64>,
+    pub pv: Vec<String>, // principal variation in long algebraic notation
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UciEvent {
+    Info(UciInfo),
+    BestMove { best: String, ponder: Option<String> },
+}
+
+/// How long the engine may think for a `go` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GoLimit {
+    MoveTime(Duration),
+    Depth(u32),
+    Infinite,
+}
+
+impl fmt::Display for GoLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoLimit::MoveTime(time) => write!(f, "go movetime {}", time.as_millis()),
+            GoLimit::Depth(depth) => write!(f, "go depth {depth}"),
+            GoLimit::Infinite => write!(f, "go infinite"),
+        }
+    }
+}
+
+/// A running engine process. Output is read on a background thread, so `poll`
+/// never blocks and can be called every frame.
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Mutex<Receiver<String>>,
+    name: Option<String>,
+    searching: bool,
+    stopping: bool,
+}
+
+impl UciEngine {
+    /// Starts the engine at `path` and waits for it to finish the `uci` handshake.
+    pub fn start(path: &Path) -> Result<Self, UciError> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(UciError::Exited)?;
+        let stdout = child.stdout.take().ok_or(UciError::Exited)?;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Self { child, stdin, lines: Mutex::new(receiver), name: None, searching: false, stopping: false };
+        engine.send("uci")?;
+        for line in engine.wait_for("uciok", HANDSHAKE_TIMEOUT)? {
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = Some(name.trim().to_string());
+            }
+        }
+        engine.sync()?;
+        Ok(engine)
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Whether a `go` was sent that has not been answered with `bestmove` yet.
+    pub fn is_searching(&self) -> bool {
+        self.searching
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), UciError> {
+        self.send(&format!("setoption name {name} value {value}"))
+    }
+
+    /// Tells the engine a new game starts and waits until it is ready for it.
+    pub fn new_game(&mut self) -> Result<(), UciError> {
+        self.send("ucinewgame")?;
+        self.sync()
+    }
+
+    /// Sends the game so far and starts a search on it. The answer arrives through `poll`.
+    pub fn go(&mut self, start_fen: &str, moves: &[Move], limit: GoLimit) -> Result<(), UciError> {
+        self.send(&position_command(start_fen, moves))?;
+        self.send(&limit.to_string())?;
+        self.searching = true;
+        Ok(())
+    }
+
+    /// Asks the engine to finish its search; it still answers with `bestmove`.
+    /// Does nothing when no search is running or it was already asked to stop.
+    pub fn stop(&mut self) -> Result<(), UciError> {
+        if !self.searching || self.stopping {
+            return Ok(());
+        }
+        self.stopping = true;
+        self.send("stop")
+    }
+
+    /// Everything the engine has said since the last call that the game cares about.
+    pub fn poll(&mut self) -> Result<Vec<UciEvent>, UciError> {
+        let mut events = Vec::new();
+        loop {
+            let line = match self.lines.get_mut().map_err(|_| UciError::Exited)?.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(UciError::Exited),
+            };
+            if let Some(event) = parse_line(&line) {
+                if matches!(event, UciEvent::BestMove { .. }) {
+                    self.searching = false;
+                    self.stopping = false;
+                }
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+
+    /// Blocks until the running search answers with `bestmove`, returning it with the last `info` seen.
+    pub fn wait_for_best_move(&mut self, timeout: Duration) -> Result<(String, Option<UciInfo>), UciError> {
+        let mut last_info = None;
+        for line in self.wait_for("bestmove", timeout)? {
+            match parse_line(&line) {
+                Some(UciEvent::Info(info)) => last_info = Some(info),
+                Some(UciEvent::BestMove { best, .. }) => {
+                    self.searching = false;
+                    self.stopping = false;
+                    return Ok((best, last_info));
+                }
+                None => {}
+            }
+        }
+        Err(UciError::Timeout("bestmove"))
+    }
+
+    /// Round trip through `isready`/`readyok`.
+    fn sync(&mut self) -> Result<(), UciError> {
+        self.send("isready")?;
+        self.wait_for("readyok", HANDSHAKE_TIMEOUT).map(|_| ())
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), UciError> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    /// Collects lines until one starts with `reply`, which is included as the last line.
+    fn wait_for(&mut self, reply: &'static str, timeout: Duration) -> Result<Vec<String>, UciError> {
+        let deadline = Instant::now() + timeout;
+        let lines = self.lines.get_mut().map_err(|_| UciError::Exited)?;
+        let mut received = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match lines.recv_timeout(remaining) {
+                Ok(line) => {
+                    let done = line.split_whitespace().next() == Some(reply);
+                    received.push(line);
+                    if done {
+                        return Ok(received);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Err(UciError::Timeout(reply)),
+                Err(RecvTimeoutError::Disconnected) => return Err(UciError::Exited),
+            }
+        }
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // Give the engine a moment to quit on its own before it is killed
+        let deadline = Instant::now() + Duration::from_millis(200);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// `position` command for the game that started at `start_fen` and continued with `moves`.
+pub fn position_command(start_fen: &str, moves: &[Move]) -> String {
+    let mut command = if start_fen == crate::fen::STARTING_FEN {
+        "position startpos".to_string()
+    } else {
+        format!("position fen {start_fen}")
+    };
+    if !moves.is_empty() {
+        command.push_str(" moves");
+        for mv in moves {
+            command.push_str(&format!(" {mv}"));
+        }
+    }
+    command
+}
+
+/// Parses an `info` or `bestmove` line; anything else is of no interest and gives `None`.
+pub fn parse_line(line: &str) -> Option<UciEvent> {
+    let mut tokens = line.split_whitespace();
+    match tokens.next()? {
+        "bestmove" => {
+            let best = tokens.next()?.to_string();
+            let ponder = match tokens.next() {
+                Some("ponder") => tokens.next().map(str::to_string),
+                _ => None,
+            };
+            Some(UciEvent::BestMove { best, ponder })
+        }
+        "info" => {
+            let mut info = UciInfo::default();
+            while let Some(token) = tokens.next() {
+                match token {
+                    "depth" => info.depth = tokens.next().and_then(|value| value.parse().ok()),
+                    "nodes" => info.nodes = tokens.next().and_then(|value| value.parse().ok()),
+                    "score" => {
+                        info.score = match (tokens.next(), tokens.next().and_then(|value| value.parse().ok())) {
+                            (Some("cp"), Some(value)) => Some(UciScore::Centipawns(value)),
+                            (Some("mate"), Some(value)) => Some(UciScore::Mate(value)),
+                            _ => None,
+                        };
+                    }
+                    // The principal variation runs to the end of the line
+                    "pv" => {
+                        info.pv = tokens.by_ref().map(str::to_string).collect();
+                    }
+                    // Free text, nothing useful can follow
+                    "string" => break,
+                    _ => {}
+                }
+            }
+            Some(UciEvent::Info(info))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::fen::STARTING_FEN;
+    use crate::notation::parse_uci;
+    use crate::rules::Position;
+    use crate::test_files::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    /// A shell script that answers like an engine which always plays e2e4, in a
+    /// directory of its own that goes away with it.
+    struct StandInEngine {
+        path: PathBuf,
+        _directory: TempDir,
+    }
+
+    fn stand_in_engine(name: &str) -> StandInEngine {
+        let directory = TempDir::new(&format!("stand_in_engine_{name}"));
+        let engine = StandInEngine { path: directory.path().join("engine.sh"), _directory: directory };
+        std::fs::write(&engine.path, r#"#!/bin/sh
+while read -r line; do
+    case "$line" in
+        uci) echo "id name Stand-in 1.0"; echo "id author Nobody"; echo "uciok" ;;
+        isready) echo "readyok" ;;
+        "go infinite") ;;
+        go*) echo "info depth 1 score cp 12 nodes 20 pv d2d4"
+             echo "info depth 2 score cp 25 nodes 400 pv e2e4 e7e5"
+             echo "bestmove e2e4 ponder e7e5" ;;
+        stop) echo "bestmove d2d4" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#).unwrap();
+        std::fs::set_permissions(&engine.path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        engine
+    }
+
+    #[test]
+    fn handshake_reads_the_engine_name() {
+        let stand_in = stand_in_engine("handshake");
+        let mut engine = UciEngine::start(&stand_in.path).unwrap();
+        assert_eq!(engine.name(), Some("Stand-in 1.0"));
+        engine.new_game().unwrap();
+        assert!(!engine.is_searching());
+    }
+
+    #[test]
+    fn search_returns_best_move_and_last_info() {
+        let stand_in = stand_in_engine("search");
+        let mut engine = UciEngine::start(&stand_in.path).unwrap();
+
+        engine.go(STARTING_FEN, &[], GoLimit::MoveTime(Duration::from_millis(100))).unwrap();
+        assert!(engine.is_searching());
+        let (best, info) = engine.wait_for_best_move(Duration::from_secs(5)).unwrap();
+        assert!(!engine.is_searching());
+
+        assert_eq!(best, "e2e4");
+        let info = info.unwrap();
+        assert_eq!(info.depth, Some(2));
+        assert_eq!(info.score, Some(UciScore::Centipawns(25)));
+        assert_eq!(info.pv, vec!["e2e4", "e7e5"]);
+
+        // The answer is a legal move in the position that was sent
+        assert!(parse_uci(&Position::starting(), &best).is_ok());
+    }
+
+    #[test]
+    fn stop_ends_an_infinite_search_through_poll() {
+        let stand_in = stand_in_engine("stop");
+        let mut engine = UciEngine::start(&stand_in.path).unwrap();
+
+        engine.go(STARTING_FEN, &[], GoLimit::Infinite).unwrap();
+        engine.stop().unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = Vec::new();
+        while engine.is_searching() && Instant::now() < deadline {
+            events.extend(engine.poll().unwrap());
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(events, vec![UciEvent::BestMove { best: "d2d4".to_string(), ponder: None }]);
+    }
+
+    #[test]
+    fn missing_engine_is_an_error() {
+        assert!(UciEngine::start(Path::new("/nonexistent/engine")).is_err());
+    }
+
+    #[test]
+    fn position_command_lists_moves_in_long_algebraic() {
+        let mut position = Position::starting();
+        let moves: Vec<Move> = ["e2e4", "e7e5", "g1f3"].iter().map(|text| {
+            let mv = parse_uci(&position, text).unwrap();
+            position.make_move(mv);
+            mv
+        }).collect();
+
+        assert_eq!(position_command(STARTING_FEN, &[]), "position startpos");
+        assert_eq!(position_command(STARTING_FEN, &moves), "position startpos moves e2e4 e7e5 g1f3");
+        assert_eq!(
+            position_command("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", &[]),
+            "position fen 4k3/P7/8/8/8/8/8/4K3 w - - 0 1",
+        );
+    }
+
+    #[test]
+    fn parses_info_and_bestmove_lines() {
+        assert_eq!(
+            parse_line("info depth 12 seldepth 18 multipv 1 score mate -3 nodes 123456 nps 1000 pv h7h8q g8h8"),
+            Some(UciEvent::Info(UciInfo {
+                depth: Some(12),
+                score: Some(UciScore::Mate(-3)),
+                nodes: Some(123456),
+                pv: vec!["h7h8q".to_string(), "g8h8".to_string()],
+            })),
+        );
+        assert_eq!(
+            parse_line("info string depth 3 means nothing here"),
+            Some(UciEvent::Info(UciInfo::default())),
+        );
+        assert_eq!(
+            parse_line("bestmove e7e8n ponder d2d1"),
+            Some(UciEvent::BestMove { best: "e7e8n".to_string(), ponder: Some("d2d1".to_string()) }),
+        );
+        assert_eq!(parse_line("readyok"), None);
+    }
+}
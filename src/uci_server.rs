@@ -0,0 +1,445 @@
+//! The built-in engine speaking UCI on stdin/stdout, for use from other chess GUIs
+//! and tournament managers. Started with `--uci`; no window is opened.
+
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::notation::parse_uci;
+use crate::rules::*;
+use crate::search::*;
+
+const ENGINE_NAME: &str = "Bevy Chess";
+const ENGINE_AUTHOR: &str = "William Simonak";
+
+/// Stands in for "no time limit" in `go depth` and `go infinite`.
+const UNLIMITED_TIME: Duration = Duration::from_secs(24 * 60 * 60);
+/// Kept back from the clock for the GUI to receive and play the move.
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+/// Moves the remaining time is spread over when the GUI does not send `movestogo`.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// Where replies go. The search thread writes its `info` and `bestmove` lines too.
+type Output = Arc<Mutex<dyn Write + Send>>;
+
+/// A search running on its own thread, so `stop` and `isready` are answered while it thinks.
+struct RunningSearch {
+    handle: JoinHandle<Searcher>,
+    stop_flag: Arc<AtomicBool>,
+    ponder_until_stopped: Arc<AtomicBool>, // `go infinite` must not answer before `stop`
+}
+
+struct UciServer {
+    output: Output,
+    position: Position,
+    difficulty: Option<Difficulty>, // None plays at full strength
+    searcher: Option<Searcher>, // handed to the search thread while one runs
+    running: Option<RunningSearch>,
+}
+
+/// Reads UCI commands from stdin until `quit` or the end of input.
+pub fn run() {
+    serve(io::stdin().lock(), io::stdout());
+}
+
+/// Answers the UCI commands read from `input` on `output` until `quit` or the end of input.
+pub fn serve(input: impl BufRead, output: impl Write + Send + 'static) {
+    let mut server = UciServer {
+        output: Arc::new(Mutex::new(output)),
+        position: Position::starting(),
+        difficulty: None,
+        searcher: Some(Searcher::new()),
+        running: None,
+    };
+
+    for line in input.lines() {
+        let Ok(line) = line else { break };
+        if !server.handle(&line) {
+            break;
+        }
+    }
+    server.stop_search();
+}
+
+/// Writes one line and flushes it, so the GUI sees it straight away.
+fn say(output: &Output, line: &str) {
+    let mut output = output.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Nobody left to tell if the GUI has gone away
+    let _ = writeln!(output, "{line}").and_then(|()| output.flush());
+}
+
+impl UciServer {
+    /// Carries out one command, returning false once the engine should quit.
+    fn handle(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = tokens.split_first() else { return true };
+
+        match command {
+            "uci" => {
+                say(&self.output, &format!("id name {ENGINE_NAME}"));
+                say(&self.output, &format!("id author {ENGINE_AUTHOR}"));
+                let levels: Vec<String> = Difficulty::ALL.iter().map(|level| format!("var {}", level.name())).collect();
+                say(&self.output, &format!("option name Difficulty type combo default Full var Full {}", levels.join(" ")));
+                say(&self.output, "uciok");
+            }
+            "isready" => say(&self.output, "readyok"),
+            "ucinewgame" => {
+                self.stop_search();
+                self.searcher = Some(Searcher::new());
+                self.position = Position::starting();
+            }
+            "setoption" => self.set_option(arguments),
+            "position" => {
+                self.stop_search();
+                self.set_position(arguments);
+            }
+            "go" => {
+                self.stop_search();
+                self.go(arguments);
+            }
+            "stop" => self.stop_search(),
+            "quit" => return false,
+            // `debug`, `register`, `ponderhit` and anything unknown are ignored, as the protocol asks
+            _ => {}
+        }
+        true
+    }
+
+    /// `setoption name <name> value <value>`; names may contain spaces.
+    fn set_option(&mut self, arguments: &[&str]) {
+        let text = arguments.join(" ");
+        let Some(text) = text.strip_prefix("name ") else { return };
+        let (name, value) = text.split_once(" value ").unwrap_or((text, ""));
+
+        if name.trim().eq_ignore_ascii_case("Difficulty") {
+            let value = value.trim();
+            self.difficulty = Difficulty::ALL.into_iter().find(|level| level.name().eq_ignore_ascii_case(value));
+            if self.difficulty.is_none() && !value.eq_ignore_ascii_case("Full") {
+                say(&self.output, &format!("info string unknown difficulty {value}, playing at full strength"));
+            }
+        } else {
+            say(&self.output, &format!("info string unknown option {}", name.trim()));
+        }
+    }
+
+    /// `position startpos|fen <fen> [moves <move>...]`
+    fn set_position(&mut self, arguments: &[&str]) {
+        let moves_at = arguments.iter().position(|&token| token == "moves").unwrap_or(arguments.len());
+        let position = match arguments.first() {
+            Some(&"startpos") => Ok(Position::starting()),
+            Some(&"fen") => Position::from_fen(&arguments[1..moves_at].join(" ")),
+            _ => {
+                say(&self.output, "info string expected startpos or fen");
+                return;
+            }
+        };
+        let mut position = match position {
+            Ok(position) => position,
+            Err(error) => {
+                say(&self.output, &format!("info string invalid FEN: {error}"));
+                return;
+            }
+        };
+
+        for text in arguments.iter().skip(moves_at + 1) {
+            match parse_uci(&position, text) {
+                Ok(mv) => position.make_move(mv),
+                Err(error) => {
+                    say(&self.output, &format!("info string {error}"));
+                    break;
+                }
+            }
+        }
+        self.position = position;
+    }
+
+    /// `go [depth N] [movetime N] [wtime N] [btime N] [winc N] [binc N] [movestogo N] [infinite]`
+    fn go(&mut self, arguments: &[&str]) {
+        let GoOptions { depth, time_limit, infinite } = GoOptions::parse(arguments, self.position.side_to_move());
+        let mut limits = match self.difficulty {
+            Some(level) => SearchLimits { time_limit: SearchLimits::preset(level).time_limit.min(time_limit), ..SearchLimits::preset(level) },
+            None => SearchLimits::timed(time_limit),
+        };
+        if let Some(depth) = depth {
+            limits.max_depth = limits.max_depth.min(depth.max(1));
+        }
+
+        let mut searcher = self.searcher.take().unwrap_or_default();
+        let stop_flag = searcher.stop_flag();
+        stop_flag.store(false, Ordering::Relaxed);
+        let ponder_until_stopped = Arc::new(AtomicBool::new(infinite));
+        let mut position = self.position.clone();
+
+        let waiting = ponder_until_stopped.clone();
+        let stopped = stop_flag.clone();
+        let output = self.output.clone();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let result = searcher.search_with_report(&mut position, limits, |result, pv| {
+                say(&output, &info_line(result, pv, start.elapsed()));
+            });
+
+            while waiting.load(Ordering::Relaxed) && !stopped.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(5));
+            }
+
+            match result.best_move {
+                Some(mv) => say(&output, &format!("bestmove {mv}")),
+                None => say(&output, "bestmove 0000"),
+            }
+            searcher
+        });
+
+        self.running = Some(RunningSearch { handle, stop_flag, ponder_until_stopped });
+    }
+
+    /// Stops the running search, if any, and waits for it to print its `bestmove`.
+    fn stop_search(&mut self) {
+        let Some(running) = self.running.take() else { return };
+        running.stop_flag.store(true, Ordering::Relaxed);
+        running.ponder_until_stopped.store(false, Ordering::Relaxed);
+        self.searcher = Some(running.handle.join().unwrap_or_default());
+    }
+}
+
+/// What a `go` command asks for.
+#[derive(Debug, PartialEq)]
+struct GoOptions {
+    depth: Option<u32>,
+    time_limit: Duration,
+    infinite: bool,
+}
+
+impl GoOptions {
+    /// Reads the `go` arguments, turning a clock into the time to spend on this move.
+    fn parse(arguments: &[&str], side_to_move: PieceColor) -> Self {
+        let mut depth = None;
+        let mut move_time = None;
+        let mut clock = [None, None]; // white, black remaining time
+        let mut increment = [0, 0];
+        let mut moves_to_go = None;
+        let mut infinite = false;
+
+        let mut tokens = arguments.iter();
+        while let Some(&token) = tokens.next() {
+            let mut value = || tokens.next().and_then(|value| value.parse::<u64>().ok());
+            match token {
+                "depth" => depth = value().map(|depth| depth as u32),
+                "movetime" => move_time = value(),
+                "wtime" => clock[0] = value(),
+                "btime" => clock[1] = value(),
+                "winc" => increment[0] = value().unwrap_or(0),
+                "binc" => increment[1] = value().unwrap_or(0),
+                "movestogo" => moves_to_go = value().map(|moves| moves as u32),
+                "infinite" => infinite = true,
+                _ => {}
+            }
+        }
+
+        let side = match side_to_move {
+            PieceColor::White => 0,
+            PieceColor::Black => 1,
+        };
+        let time_limit = if infinite {
+            UNLIMITED_TIME
+        } else if let Some(move_time) = move_time {
+            Duration::from_millis(move_time)
+        } else if let Some(remaining) = clock[side] {
+            // An even share of what is left plus most of the increment, never the whole clock
+            let remaining = Duration::from_millis(remaining);
+            let share = remaining / moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1)
+                + Duration::from_millis(increment[side]) * 3 / 4;
+            share.min(remaining.saturating_sub(MOVE_OVERHEAD)).max(Duration::from_millis(1))
+        } else {
+            UNLIMITED_TIME
+        };
+
+        Self { depth, time_limit, infinite }
+    }
+}
+
+fn info_line(result: &SearchResult, pv: &[Move], elapsed: Duration) -> String {
+    let score = if result.score.abs() > MATE_THRESHOLD {
+        // Plies to mate, rounded up to whole moves
+        let plies = MATE_SCORE - result.score.abs();
+        let moves = (plies + 1) / 2;
+        format!("mate {}", if result.score > 0 { moves } else { -moves })
+    } else {
+        format!("cp {}", result.score)
+    };
+
+    let millis = elapsed.as_millis().max(1);
+    let mut line = format!(
+        "info depth {} score {score} nodes {} time {millis} nps {}",
+        result.depth,
+        result.nodes,
+        result.nodes as u128 * 1000 / millis,
+    );
+    if !pv.is_empty() {
+        line.push_str(" pv");
+        for mv in pv {
+            line.push_str(&format!(" {mv}"));
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, PipeWriter};
+
+    /// Collects what the server writes, for the test to read while it is still running.
+    #[derive(Clone, Default)]
+    struct Transcript(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Transcript {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// `serve` running on its own thread, fed through a pipe.
+    struct Session {
+        commands: Option<PipeWriter>,
+        transcript: Transcript,
+        read: usize, // lines already looked at by `wait_for`
+        server: Option<JoinHandle<()>>,
+    }
+
+    impl Session {
+        fn start() -> Self {
+            let (reader, writer) = io::pipe().unwrap();
+            let transcript = Transcript::default();
+            let output = transcript.clone();
+            let server = thread::spawn(move || serve(BufReader::new(reader), output));
+            Self { commands: Some(writer), transcript, read: 0, server: Some(server) }
+        }
+
+        fn send(&mut self, command: &str) {
+            writeln!(self.commands.as_mut().unwrap(), "{command}").unwrap();
+        }
+
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.transcript.0.lock().unwrap().clone()).unwrap().lines().map(str::to_string).collect()
+        }
+
+        /// The next line starting with `prefix`, waiting up to ten seconds for it.
+        fn wait_for(&mut self, prefix: &str) -> String {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let lines = self.lines();
+                if let Some(at) = lines.iter().skip(self.read).position(|line| line.starts_with(prefix)) {
+                    self.read += at + 1;
+                    return lines[self.read - 1].clone();
+                }
+                assert!(Instant::now() < deadline, "no {prefix:?} in {lines:#?}");
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+    }
+
+    impl Drop for Session {
+        fn drop(&mut self) {
+            // Closing the pipe ends `serve` like a GUI going away
+            self.commands = None;
+            if let Some(server) = self.server.take() {
+                let _ = server.join();
+            }
+        }
+    }
+
+    #[test]
+    fn plays_from_the_start_position_after_the_moves_given() {
+        let mut session = Session::start();
+        session.send("position startpos moves f2f3 e7e5 g2g4");
+        session.send("go depth 3");
+        assert_eq!(session.wait_for("bestmove"), "bestmove d8h4");
+    }
+
+    #[test]
+    fn plays_from_a_fen_after_the_moves_given() {
+        // After Ra7 the black king has a single move left
+        let mut session = Session::start();
+        session.send("position fen 7k/8/8/8/8/8/8/R5K1 w - - 0 1 moves a1a7");
+        session.send("go depth 2");
+        assert_eq!(session.wait_for("bestmove"), "bestmove h8g8");
+    }
+
+    #[test]
+    fn reports_bad_positions_and_moves() {
+        let mut session = Session::start();
+        session.send("position fen 7k/8/8/8/8/8/8/R5K1 w - - 0 1 moves a1a7");
+        session.send("position fen 7k/8/8/8 w - - 0 1");
+        session.wait_for("info string invalid FEN");
+        session.send("go depth 2");
+        assert_eq!(session.wait_for("bestmove"), "bestmove h8g8", "a bad FEN leaves the position alone");
+
+        // Moves are played up to the first one that is not legal
+        session.send("position fen 7k/8/8/8/8/8/8/R5K1 w - - 0 1 moves a1a7 h8h7 g1g2");
+        session.wait_for("info string");
+        session.send("go depth 2");
+        assert_eq!(session.wait_for("bestmove"), "bestmove h8g8");
+    }
+
+    #[test]
+    fn difficulty_is_an_option() {
+        let mut session = Session::start();
+        session.send("uci");
+        assert!(session.wait_for("option name Difficulty").contains("var Beginner"));
+        session.wait_for("uciok");
+
+        session.send("setoption name Difficulty value Grandmaster");
+        session.wait_for("info string unknown difficulty Grandmaster");
+
+        // Beginner never looks further than one ply, whatever depth is asked for
+        session.send("setoption name Difficulty value Beginner");
+        session.send("go depth 20");
+        session.wait_for("bestmove");
+        let depths: Vec<u32> = session.lines().iter()
+            .filter_map(|line| line.strip_prefix("info depth ")?.split(' ').next()?.parse().ok())
+            .collect();
+        assert!(!depths.is_empty() && depths.iter().all(|&depth| depth == 1), "{depths:?}");
+    }
+
+    #[test]
+    fn infinite_search_answers_only_once_stopped() {
+        let mut session = Session::start();
+        session.send("position startpos");
+        session.send("go infinite");
+        session.send("isready");
+        session.wait_for("readyok");
+        assert!(!session.lines().iter().any(|line| line.starts_with("bestmove")));
+
+        session.send("stop");
+        let best = session.wait_for("bestmove");
+        let uci = best.strip_prefix("bestmove ").unwrap();
+        assert!(parse_uci(&Position::starting(), uci).is_ok(), "{best}");
+    }
+
+    #[test]
+    fn splits_the_clock_over_the_moves_to_go() {
+        let time = |arguments: &str, side| GoOptions::parse(&arguments.split_whitespace().collect::<Vec<_>>(), side).time_limit;
+        let clock = "wtime 60000 btime 3000 winc 1000 binc 0";
+
+        // A thirtieth of the clock when movestogo is not sent, plus three quarters of the increment
+        assert_eq!(time(clock, PieceColor::White), Duration::from_millis(2750));
+        assert_eq!(time(clock, PieceColor::Black), Duration::from_millis(100));
+        assert_eq!(time(&format!("{clock} movestogo 10"), PieceColor::White), Duration::from_millis(6750));
+
+        // The last move before the time control may not spend the whole clock
+        assert_eq!(time("wtime 1000 movestogo 1", PieceColor::White), Duration::from_millis(950));
+        assert_eq!(time("wtime 30 winc 1000", PieceColor::White), Duration::from_millis(1));
+
+        assert_eq!(time(&format!("{clock} movetime 500"), PieceColor::White), Duration::from_millis(500));
+        let options = GoOptions::parse(&["depth", "6"], PieceColor::White);
+        assert_eq!(options, GoOptions { depth: Some(6), time_limit: UNLIMITED_TIME, infinite: false });
+        assert!(GoOptions::parse(&["infinite"], PieceColor::Black).infinite);
+    }
+}
@@ -0,0 +1,526 @@
+use bevy::prelude::*;
+
+use crate::config::*;
+use crate::resource_allocation::*;
+use crate::pieces::*;
+use crate::rules::*;
+use crate::replay::*;
+use crate::search::Difficulty;
+use crate::notation::*;
+use crate::uci::*;
+use crate::tablebase::Wdl;
+
+
+#[derive(Component)]
+pub struct TurnIndicator;
+
+#[derive(Component)]
+pub struct GameOverText;
+
+#[derive(Component)]
+pub struct DrawClaimText;
+
+#[derive(Component)]
+pub struct SettingsText;
+
+#[derive(Component)]
+pub struct ReplayText;
+
+#[derive(Component)]
+pub struct MoveListText;
+
+#[derive(Component)]
+pub struct EngineText;
+
+#[derive(Component)]
+pub struct BookText;
+
+#[derive(Component)]
+pub struct TablebaseText;
+
+#[derive(Component)]
+pub struct PromotionOverlay;
+
+/// Button in the promotion picker, holding the piece it promotes to.
+#[derive(Component)]
+pub struct PromotionChoice(pub PieceType);
+
+/// Button picking the computer's strength.
+#[derive(Component)]
+pub struct DifficultyChoice(pub Difficulty);
+
+
+pub fn create_ui(
+    mut commands: Commands,
+) {
+    // Side panel to the right of the board
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(HUD_MARGIN),
+            left: Val::Px(BOARD_SIZE + HUD_MARGIN),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(HUD_MARGIN / 2.0),
+            ..default()
+        },
+    )).with_children(|panel| {
+        panel.spawn((
+            Text::new(turn_text(PieceColor::White, false)),
+            TextFont { font_size: HUD_FONT_SIZE, ..default() },
+            TextColor(HUD_TEXT_COLOR),
+            TurnIndicator,
+        ));
+        panel.spawn((
+            Text::new(""),
+            TextFont { font_size: HUD_FONT_SIZE, ..default() },
+            TextColor(HUD_HIGHLIGHT_COLOR),
+            GameOverText,
+        ));
+        panel.spawn((
+            Text::new(""),
+            TextFont { font_size: HUD_FONT_SIZE * 0.6, ..default() },
+            TextColor(HUD_HIGHLIGHT_COLOR),
+            DrawClaimText,
+        ));
+        panel.spawn((
+            Text::new(settings_text(AUTO_QUEEN, None)),
+            TextFont { font_size: HUD_FONT_SIZE * 0.6, ..default() },
+            TextColor(HUD_TEXT_COLOR),
+            SettingsText,
+        ));
+        panel.spawn(Node {
+            flex_direction: FlexDirection::Row,
+            column_gap: Val::Px(HUD_MARGIN / 4.0),
+            ..default()
+        }).with_children(|buttons| {
+            for difficulty in Difficulty::ALL {
+                buttons.spawn((
+                    Button,
+                    Node {
+                        padding: UiRect::axes(Val::Px(6.0), Val::Px(3.0)),
+                        ..default()
+                    },
+                    BackgroundColor(HUD_BUTTON_COLOR),
+                    DifficultyChoice(difficulty),
+                )).with_child((
+                    Text::new(difficulty.name()),
+                    TextFont { font_size: HUD_FONT_SIZE * 0.5, ..default() },
+                    TextColor(HUD_TEXT_COLOR),
+                ));
+            }
+        });
+
+        panel.spawn((
+            Text::new(""),
+            TextFont { font_size: HUD_FONT_SIZE * 0.6, ..default() },
+            TextColor(HUD_TEXT_COLOR),
+            Node { max_width: Val::Px(WINDOW_WIDTH - BOARD_SIZE - 2.0 * HUD_MARGIN), ..default() },
+            ReplayText,
+        ));
+        panel.spawn((
+            Text::new(""),
+            TextFont { font_size: HUD_FONT_SIZE * 0.6, ..default() },
+            TextColor(HUD_TEXT_COLOR),
+            MoveListText,
+        ));
+        panel.spawn((
+            Text::new(""),
+            TextFont { font_size: HUD_FONT_SIZE * 0.6, ..default() },
+            TextColor(HUD_TEXT_COLOR),
+            Node { max_width: Val::Px(WINDOW_WIDTH - BOARD_SIZE - 2.0 * HUD_MARGIN), ..default() },
+            EngineText,
+        ));
+        panel.spawn((
+            Text::new(""),
+            TextFont { font_size: HUD_FONT_SIZE * 0.6, ..default() },
+            TextColor(HUD_TEXT_COLOR),
+            BookText,
+        ));
+        panel.spawn((
+            Text::new(""),
+            TextFont { font_size: HUD_FONT_SIZE * 0.6, ..default() },
+            TextColor(HUD_TEXT_COLOR),
+            TablebaseText,
+        ));
+
+        // Promotion picker, hidden until a pawn reaches the last rank
+        panel.spawn((
+            Node {
+                display: Display::None,
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(HUD_MARGIN / 2.0),
+                ..default()
+            },
+            PromotionOverlay,
+        )).with_children(|overlay| {
+            overlay.spawn((
+                Text::new("Promote to:"),
+                TextFont { font_size: HUD_FONT_SIZE, ..default() },
+                TextColor(HUD_HIGHLIGHT_COLOR),
+            ));
+            overlay.spawn(Node {
+                flex_direction: FlexDirection::Row,
+                column_gap: Val::Px(HUD_MARGIN / 2.0),
+                ..default()
+            }).with_children(|buttons| {
+                for piece_type in PROMOTION_PIECES {
+                    buttons.spawn((
+                        Button,
+                        Node {
+                            width: Val::Px(PROMOTION_BUTTON_SIZE),
+                            height: Val::Px(PROMOTION_BUTTON_SIZE),
+                            ..default()
+                        },
+                        BackgroundColor(HUD_BUTTON_COLOR),
+                        ImageNode::default(),
+                        PromotionChoice(piece_type),
+                    ));
+                }
+            });
+        });
+    });
+}
+
+pub fn update_turn_indicator(
+    game_state: Res<GameState>,
+    mut indicator_query: Query<&mut Text, With<TurnIndicator>>,
+) {
+    if !game_state.is_changed() {
+        return;
+    }
+
+    let in_check = game_state.position.is_in_check();
+    for mut text in indicator_query.iter_mut() {
+        text.0 = turn_text(game_state.position.side_to_move(), in_check);
+    }
+}
+
+pub fn update_game_over_text(
+    game_result: Res<GameResult>,
+    mut text_query: Query<&mut Text, With<GameOverText>>,
+) {
+    if !game_result.is_changed() {
+        return;
+    }
+
+    let message = game_result.outcome.map(outcome_text).unwrap_or_default();
+    for mut text in text_query.iter_mut() {
+        text.0 = message.clone();
+    }
+}
+
+pub fn update_draw_claim_text(
+    game_state: Res<GameState>,
+    game_result: Res<GameResult>,
+    mut text_query: Query<&mut Text, With<DrawClaimText>>,
+) {
+    if !game_state.is_changed() && !game_result.is_changed() {
+        return;
+    }
+
+    let claimable = game_state.position.claimable_draw().filter(|_| game_result.outcome.is_none());
+    let message = match claimable {
+        Some(DrawReason::FiftyMoveRule) => "Fifty-move rule: claim a draw [D]".to_string(),
+        Some(DrawReason::ThreefoldRepetition) => "Threefold repetition: claim a draw [D]".to_string(),
+        _ => String::new(),
+    };
+    for mut text in text_query.iter_mut() {
+        text.0 = message.clone();
+    }
+}
+
+pub fn update_promotion_overlay(
+    game_state: Res<GameState>,
+    piece_sprites: Option<Res<PieceSprites>>,
+    mut overlay_query: Query<&mut Node, With<PromotionOverlay>>,
+    mut button_query: Query<(&PromotionChoice, &Interaction, &mut ImageNode, &mut BackgroundColor)>,
+) {
+    for (_, interaction, _, mut background) in button_query.iter_mut() {
+        let color = if *interaction == Interaction::None { HUD_BUTTON_COLOR } else { HUD_BUTTON_HOVER_COLOR };
+        if background.0 != color {
+            background.0 = color;
+        }
+    }
+
+    if !game_state.is_changed() {
+        return;
+    }
+
+    let display = if game_state.pending_promotion.is_some() { Display::Flex } else { Display::None };
+    for mut node in overlay_query.iter_mut() {
+        node.display = display;
+    }
+
+    // Show the pieces in the colour of the side that is promoting
+    let Some(piece_sprites) = piece_sprites else { return };
+    let color = game_state.position.side_to_move();
+    for (choice, _, mut image, _) in button_query.iter_mut() {
+        image.image = piece_sprites.get(choice.0, color);
+    }
+}
+
+pub fn update_settings_text(
+    game_settings: Res<GameSettings>,
+    ai_player: Res<AiPlayer>,
+    mut text_query: Query<&mut Text, With<SettingsText>>,
+) {
+    if !game_settings.is_changed() && !ai_player.is_changed() {
+        return;
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.0 = settings_text(game_settings.auto_queen, ai_player.color);
+    }
+}
+
+/// Highlights the chosen difficulty and the button under the cursor.
+pub fn update_difficulty_buttons(
+    ai_difficulty: Res<AiDifficulty>,
+    mut button_query: Query<(&DifficultyChoice, &Interaction, &mut BackgroundColor)>,
+) {
+    for (choice, interaction, mut background) in button_query.iter_mut() {
+        let color = if choice.0 == ai_difficulty.level {
+            HUD_BUTTON_SELECTED_COLOR
+        } else if *interaction == Interaction::None {
+            HUD_BUTTON_COLOR
+        } else {
+            HUD_BUTTON_HOVER_COLOR
+        };
+        if background.0 != color {
+            background.0 = color;
+        }
+    }
+}
+
+pub fn update_replay_text(
+    replay: Res<Replay>,
+    mut text_query: Query<&mut Text, With<ReplayText>>,
+) {
+    if !replay.is_changed() {
+        return;
+    }
+
+    let message = match replay.current_game() {
+        Some(game) => {
+            let mut message = format!(
+                "Replay {}/{}: {} vs {}\nMove {}/{}",
+                replay.game_index + 1,
+                replay.games.len(),
+                game.header("White").unwrap_or("?"),
+                game.header("Black").unwrap_or("?"),
+                replay.cursor,
+                replay.moves.len(),
+            );
+            if let Some(comment) = game.comment_at(replay.cursor) {
+                message.push_str(&format!("\n{comment}"));
+            }
+            message.push_str("\n[Left/Right] step  [Home/End] ends\n[PgUp/PgDn] game  [Enter] play on");
+            message
+        }
+        None => "Drop a PGN file on the window to replay it".to_string(),
+    };
+    for mut text in text_query.iter_mut() {
+        text.0 = message.clone();
+    }
+}
+
+pub fn update_move_list_text(
+    move_history: Res<MoveHistory>,
+    mut text_query: Query<&mut Text, With<MoveListText>>,
+) {
+    if !move_history.is_changed() {
+        return;
+    }
+
+    let message = move_list_text(&move_history);
+    for mut text in text_query.iter_mut() {
+        text.0 = message.clone();
+    }
+}
+
+/// The last `MOVE_LIST_LINES` full moves in SAN, one numbered line per move pair.
+fn move_list_text(move_history: &MoveHistory) -> String {
+    let start = Position::from_fen(&move_history.start_fen).unwrap_or_else(|_| Position::starting());
+    let mut move_number = start.fullmove_number();
+    let mut lines = Vec::new();
+
+    let mut sans = move_history.moves.iter().map(|(_, san)| san.as_str());
+    if start.side_to_move() == PieceColor::Black && let Some(san) = sans.next() {
+        lines.push(format!("{move_number}. ... {san}"));
+        move_number += 1;
+    }
+    while let Some(white) = sans.next() {
+        match sans.next() {
+            Some(black) => lines.push(format!("{move_number}. {white} {black}")),
+            None => lines.push(format!("{move_number}. {white}")),
+        }
+        move_number += 1;
+    }
+
+    let skip = lines.len().saturating_sub(MOVE_LIST_LINES);
+    lines[skip..].join("\n")
+}
+
+pub fn update_engine_text(
+    game_state: Res<GameState>,
+    external: Res<ExternalEngine>,
+    mut text_query: Query<&mut Text, With<EngineText>>,
+) {
+    if !game_state.is_changed() && !external.is_changed() {
+        return;
+    }
+
+    let message = match &external.engine {
+        None => String::new(),
+        Some(engine) => {
+            let name = engine.name().unwrap_or("UCI engine");
+            match external.mode {
+                EngineMode::Off => format!("{name}: off [E]"),
+                EngineMode::Opponent => format!("{name}: plays the computer's moves [E]"),
+                EngineMode::Analysis => {
+                    let fen = game_state.position.to_fen();
+                    match &external.analysis {
+                        Some((analysed, info)) if *analysed == fen => {
+                            format!("{name}: {} [E]\n{}", score_text(&game_state.position, info), pv_text(&game_state.position, &info.pv))
+                        }
+                        _ => format!("{name}: thinking... [E]"),
+                    }
+                }
+            }
+        }
+    };
+    for mut text in text_query.iter_mut() {
+        text.0 = message.clone();
+    }
+}
+
+/// Book moves for the position on the board, with their weights and share of the total.
+pub fn update_book_text(
+    game_state: Res<GameState>,
+    book: Res<Book>,
+    mut text_query: Query<&mut Text, With<BookText>>,
+) {
+    if !game_state.is_changed() && !book.is_changed() {
+        return;
+    }
+
+    let message = match &book.book {
+        None => String::new(),
+        Some(opening_book) => {
+            let moves = opening_book.moves(&game_state.position);
+            if moves.is_empty() {
+                "Book: out of book".to_string()
+            } else {
+                let total: u32 = moves.iter().map(|(_, weight)| *weight as u32).sum();
+                let lines: Vec<String> = moves.iter().take(BOOK_MOVES_SHOWN).map(|(mv, weight)| {
+                    let share = (*weight as u32 * 100).checked_div(total).unwrap_or(0);
+                    format!("{} ({weight}, {share}%)", move_to_san(&game_state.position, *mv))
+                }).collect();
+                format!("Book moves:\n{}", lines.join("\n"))
+            }
+        }
+    };
+    for mut text in text_query.iter_mut() {
+        text.0 = message.clone();
+    }
+}
+
+/// Tablebase verdict for the side to move once the position is in the tables.
+/// N in "win in N" is DTZ, the plies until the winning side next captures or moves a pawn.
+pub fn update_tablebase_text(
+    game_state: Res<GameState>,
+    tablebase: Res<Tablebase>,
+    mut text_query: Query<&mut Text, With<TablebaseText>>,
+) {
+    if !game_state.is_changed() && !tablebase.is_changed() {
+        return;
+    }
+
+    let message = match &tablebase.tablebases {
+        Some(tablebases) if tablebases.covers(&game_state.position) => {
+            match tablebases.probe(&game_state.position) {
+                Ok(result) => {
+                    // DTZ counts plies to the next capture or pawn move, not to mate
+                    let verdict = match result.wdl {
+                        Wdl::Win => format!("win, DTZ {}", result.dtz),
+                        Wdl::CursedWin => format!("win, drawn by the fifty-move rule (DTZ {})", result.dtz),
+                        Wdl::Draw => "draw".to_string(),
+                        Wdl::BlessedLoss => format!("loss, saved by the fifty-move rule (DTZ {})", result.dtz),
+                        Wdl::Loss => format!("loss, DTZ {}", result.dtz),
+                    };
+                    format!("Tablebase: {verdict}")
+                }
+                Err(error) => format!("Tablebase: {error}"),
+            }
+        }
+        _ => String::new(),
+    };
+    for mut text in text_query.iter_mut() {
+        text.0 = message.clone();
+    }
+}
+
+/// Engine score from White's point of view, as `+0.35` or `#-3`, with the depth reached.
+fn score_text(position: &Position, info: &UciInfo) -> String {
+    let sign = if position.side_to_move() == PieceColor::White { 1 } else { -1 };
+    let score = match info.score {
+        Some(UciScore::Centipawns(centipawns)) => format!("{:+.2}", (sign * centipawns) as f32 / 100.0),
+        Some(UciScore::Mate(moves)) => format!("#{}", sign * moves),
+        None => "?".to_string(),
+    };
+    match info.depth {
+        Some(depth) => format!("{score} (depth {depth})"),
+        None => score,
+    }
+}
+
+/// The engine's principal variation in SAN, as far as it replays legally.
+fn pv_text(position: &Position, pv: &[String]) -> String {
+    let mut position = position.clone();
+    let mut text = String::new();
+    for uci in pv {
+        let Ok(mv) = parse_uci(&position, uci) else { break };
+        if position.side_to_move() == PieceColor::White {
+            text.push_str(&format!("{}. ", position.fullmove_number()));
+        } else if text.is_empty() {
+            text.push_str(&format!("{}... ", position.fullmove_number()));
+        }
+        text.push_str(&move_to_san(&position, mv));
+        text.push(' ');
+        position.make_move(mv);
+    }
+    text.trim_end().to_string()
+}
+
+fn settings_text(auto_queen: bool, ai_color: Option<PieceColor>) -> String {
+    let computer = match ai_color {
+        Some(PieceColor::White) => "plays White",
+        Some(PieceColor::Black) => "plays Black",
+        None => "off",
+    };
+    format!("Auto-queen: {} [Q]\nComputer: {computer} [A]", if auto_queen { "on" } else { "off" })
+}
+
+fn turn_text(side_to_move: PieceColor, in_check: bool) -> String {
+    let side = if side_to_move == PieceColor::White { "White" } else { "Black" };
+    if in_check {
+        format!("{side} to move - check!")
+    } else {
+        format!("{side} to move")
+    }
+}
+
+pub fn outcome_text(outcome: GameOutcome) -> String {
+    match outcome {
+        GameOutcome::Checkmate { winner: PieceColor::White } => "Checkmate - White wins".to_string(),
+        GameOutcome::Checkmate { winner: PieceColor::Black } => "Checkmate - Black wins".to_string(),
+        GameOutcome::Stalemate => "Stalemate - draw".to_string(),
+        GameOutcome::Draw(reason) => match reason {
+            DrawReason::InsufficientMaterial => "Draw - insufficient material".to_string(),
+            DrawReason::SeventyFiveMoveRule => "Draw - 75-move rule".to_string(),
+            DrawReason::FivefoldRepetition => "Draw - fivefold repetition".to_string(),
+            DrawReason::FiftyMoveRule => "Draw claimed - fifty-move rule".to_string(),
+            DrawReason::ThreefoldRepetition => "Draw claimed - threefold repetition".to_string(),
+        },
+    }
+}